@@ -0,0 +1,83 @@
+use crate::compiled;
+use crate::rules::Rule;
+use std::collections::{HashMap, HashSet};
+
+// The passwords cracked by the baseline attack (the wordlist with an existing rule file) are
+// removed from the training, so that the learned rules only add new cracks on top of it.
+
+// ids of the cleartexts cracked by running the rules on the wordlist
+pub fn cracked(
+    rules: &[Vec<Rule>],
+    wordlist: &[Vec<u8>],
+    lines: &HashMap<u64, Vec<u8>>,
+    threads: usize,
+) -> HashSet<u64> {
+    let mut ids: HashMap<&[u8], Vec<u64>> = HashMap::new();
+    for (id, line) in lines {
+        ids.entry(line.as_slice()).or_default().push(*id);
+    }
+    let chunk_size = rules.len().div_ceil(threads.max(1)).max(1);
+    let ids = &ids;
+    std::thread::scope(|s| {
+        let workers: Vec<_> = rules
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    let mut executor = compiled::Executor::new();
+                    let mut found = HashSet::new();
+                    for rule in chunk {
+                        let program = compiled::compile(rule);
+                        for word in wordlist {
+                            if let Some(i) = executor.run(&program, word).and_then(|m| ids.get(m)) {
+                                found.extend(i.iter().cloned());
+                            }
+                        }
+                    }
+                    found
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::CommandRule::*;
+    use crate::rules::Rule::Command;
+
+    #[test]
+    fn baseline() {
+        let wordlist = vec![b"password".to_vec(), b"hello".to_vec()];
+        let mut lines = HashMap::new();
+        for (i, l) in [
+            "password",
+            "Password",
+            "hello1",
+            "hello1",
+            "password1",
+            "other",
+        ]
+        .iter()
+        .enumerate()
+        {
+            lines.insert(i as u64, l.as_bytes().to_vec());
+        }
+        let rules = vec![
+            vec![Command(Noop)],
+            vec![Command(Capitalize)],
+            vec![Command(Append(b'1'))],
+        ];
+        for threads in 1..4 {
+            let mut found: Vec<u64> = cracked(&rules, &wordlist, &lines, threads)
+                .into_iter()
+                .collect();
+            found.sort_unstable();
+            assert_eq!(found, vec![0, 1, 2, 3, 4]);
+        }
+    }
+}
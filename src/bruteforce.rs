@@ -0,0 +1,95 @@
+use std::collections::{HashMap, HashSet};
+
+// Cleartexts that the planned brute-force attacks would crack anyway are not used for training,
+// so that the rules target the passwords that are out of reach of brute force.
+
+// a hashcat mask, as the set of accepted characters at each position
+#[derive(Debug)]
+pub struct Mask(Vec<[bool; 256]>);
+
+fn charset(c: u8) -> Option<[bool; 256]> {
+    let mut set = [false; 256];
+    for b in 0..=255u8 {
+        set[b as usize] = match c {
+            b'l' => b.is_ascii_lowercase(),
+            b'u' => b.is_ascii_uppercase(),
+            b'd' => b.is_ascii_digit(),
+            b'h' => matches!(b, b'0'..=b'9' | b'a'..=b'f'),
+            b'H' => matches!(b, b'0'..=b'9' | b'A'..=b'F'),
+            b's' => b.is_ascii_punctuation() || b == b' ',
+            b'a' => b.is_ascii_graphic() || b == b' ',
+            b'b' => true,
+            _ => return None,
+        };
+    }
+    Some(set)
+}
+
+impl std::str::FromStr for Mask {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut positions = Vec::new();
+        let mut it = s.bytes();
+        while let Some(c) = it.next() {
+            let set = match c {
+                b'?' => match it.next() {
+                    Some(b'?') => {
+                        let mut set = [false; 256];
+                        set[b'?' as usize] = true;
+                        set
+                    }
+                    Some(cs) => charset(cs).ok_or_else(|| {
+                        format!("unsupported charset ?{} in mask {}", cs as char, s)
+                    })?,
+                    None => return Err(format!("mask {} ends with ?", s)),
+                },
+                _ => {
+                    let mut set = [false; 256];
+                    set[c as usize] = true;
+                    set
+                }
+            };
+            positions.push(set);
+        }
+        Ok(Mask(positions))
+    }
+}
+
+impl Mask {
+    pub fn matches(&self, line: &[u8]) -> bool {
+        line.len() == self.0.len() && line.iter().zip(&self.0).all(|(c, set)| set[*c as usize])
+    }
+}
+
+// the cleartexts that are no longer than max_len, or match one of the masks
+pub fn covered(lines: &HashMap<u64, Vec<u8>>, masks: &[Mask], max_len: usize) -> HashSet<u64> {
+    lines
+        .iter()
+        .filter(|(_, l)| l.len() <= max_len || masks.iter().any(|m| m.matches(l)))
+        .map(|(i, _)| *i)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks() {
+        let mask: Mask = "?u?l?l?d??".parse().unwrap();
+        assert!(mask.matches(b"Abc1?"));
+        assert!(!mask.matches(b"abc1?"));
+        assert!(!mask.matches(b"Abc1?!"));
+        assert!("?1?d".parse::<Mask>().is_err());
+        assert!("abc?".parse::<Mask>().is_err());
+
+        let masks: Vec<Mask> = vec!["?d?d?d?d?d?d".parse().unwrap()];
+        let mut lines = HashMap::new();
+        for (i, l) in ["123456", "12345a", "abc", "password"].iter().enumerate() {
+            lines.insert(i as u64, l.as_bytes().to_vec());
+        }
+        let mut ids: Vec<u64> = covered(&lines, &masks, 4).into_iter().collect();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![0, 2]);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::cleartexts::CleartextMap;
+use crate::matcher::{record_matches, MAX_POSITION};
+use crate::rules::{CommandRule, Numerical, Rule};
+use smallvec::SmallVec;
+use std::collections::{BTreeSet, HashMap};
+
+// Mixed case variants such as PassWord are only found by the generated case rules when they
+// happen to toggle the right positions. Instead, the mutated words are looked up in an index of
+// the case folded fragments, and the case changes turning them into the fragments are derived.
+
+// longer toggle chains are too specific to be worth learning
+const MAX_TOGGLES: usize = 3;
+
+pub struct FoldedIndex {
+    // lowercased fragment -> fragments containing uppercase letters
+    folded: HashMap<SmallVec<[u8; 16]>, Vec<SmallVec<[u8; 16]>>>,
+}
+
+fn capitalize(word: &[u8]) -> Vec<u8> {
+    let mut o = word.to_ascii_lowercase();
+    if let Some(c) = o.first_mut() {
+        c.make_ascii_uppercase();
+    }
+    o
+}
+
+// the shortest case changes turning the word into the fragment, that only differ by case
+fn case_changes(word: &[u8], fragment: &[u8]) -> Option<Vec<Rule>> {
+    use CommandRule::*;
+    if word == fragment {
+        return None;
+    }
+    let cmds = if fragment == word.to_ascii_uppercase().as_slice() {
+        vec![ToUpper]
+    } else if fragment == word.to_ascii_lowercase().as_slice() {
+        vec![ToLower]
+    } else if fragment == capitalize(word).as_slice() {
+        vec![Capitalize]
+    } else {
+        let toggles: Vec<CommandRule> = word
+            .iter()
+            .zip(fragment)
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(n, _)| ToggleCase(Numerical::Val(n as u8)))
+            .collect();
+        let last = match toggles.last() {
+            Some(ToggleCase(Numerical::Val(n))) => *n as usize,
+            _ => return None,
+        };
+        if toggles.len() > MAX_TOGGLES || last > MAX_POSITION {
+            return None;
+        }
+        toggles
+    };
+    Some(cmds.into_iter().map(Rule::Command).collect())
+}
+
+impl FoldedIndex {
+    pub fn new(aclear: &CleartextMap) -> Self {
+        let mut folded: HashMap<SmallVec<[u8; 16]>, Vec<SmallVec<[u8; 16]>>> = HashMap::new();
+        for fragment in aclear.keys() {
+            if fragment.iter().any(|c| c.is_ascii_uppercase()) {
+                folded
+                    .entry(fragment.to_ascii_lowercase().into())
+                    .or_default()
+                    .push(fragment.clone());
+            }
+        }
+        FoldedIndex { folded }
+    }
+
+    // records the case changes turning the mutated word into a fragment, along with the prefix
+    // and suffix of its cleartexts
+    pub fn record(
+        &self,
+        hits: &mut HashMap<Vec<Rule>, BTreeSet<u64>>,
+        rules: &[Rule],
+        mutated: &[u8],
+        aclear: &CleartextMap,
+    ) {
+        let lower = mutated.to_ascii_lowercase();
+        let mut record = |fragment: &[u8]| {
+            if let Some(changes) = case_changes(mutated, fragment) {
+                let mut currule = rules.to_vec();
+                currule.extend(changes);
+                record_matches(hits, &currule, &aclear[fragment]);
+            }
+        };
+        if let Some(fragments) = self.folded.get(lower.as_slice()) {
+            for fragment in fragments {
+                record(fragment);
+            }
+        }
+        // lowercase fragments are in the main index
+        if lower != mutated && aclear.contains_key(lower.as_slice()) {
+            record(&lower);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cleartexts;
+    use crate::rules::mutate;
+    use crate::rules::CommandRule::*;
+    use crate::rules::Numerical::*;
+    use crate::rules::Rule::Command;
+
+    #[test]
+    fn toggles() {
+        let clears = ["PassWord1", "PASSWORD", "Password", "password", "PaSsWoRd"];
+        let mut aclear = HashMap::new();
+        for (i, c) in clears.iter().enumerate() {
+            cleartexts::process_line(&mut aclear, i as u64, c.as_bytes(), 8);
+        }
+        let index = FoldedIndex::new(&aclear);
+        let mut hits = HashMap::new();
+        index.record(&mut hits, &[Command(Noop)], b"passworD", &aclear);
+        let rule = |cmds: &[CommandRule]| {
+            let mut o = vec![Command(Noop)];
+            o.extend(cmds.iter().cloned().map(Command));
+            o
+        };
+        let mut expected = HashMap::new();
+        expected.insert(
+            rule(&[
+                ToggleCase(Val(0)),
+                ToggleCase(Val(4)),
+                ToggleCase(Val(7)),
+                Append(b'1'),
+            ]),
+            BTreeSet::from([0]),
+        );
+        expected.insert(rule(&[ToUpper]), BTreeSet::from([1]));
+        expected.insert(rule(&[Capitalize]), BTreeSet::from([2]));
+        expected.insert(rule(&[ToLower]), BTreeSet::from([3]));
+        assert_eq!(hits, expected);
+        for (rs, ids) in hits {
+            for id in ids {
+                let cleartext = mutate(b"passworD", &rs).unwrap();
+                assert_eq!(cleartext, clears[id as usize].as_bytes());
+            }
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::edits;
 use indicatif::ProgressBar;
 use smallvec::SmallVec;
 use smallvec::ToSmallVec;
@@ -10,6 +11,26 @@ pub type CleartextInfo = (SmallVec<[u8; 16]>, SmallVec<[u8; 16]>, u64);
 
 pub type CleartextMap = HashMap<SmallVec<[u8; 16]>, Vec<CleartextInfo>>;
 
+// the corpus of a cleartext is stored in the high bits of its id
+const CORPUS_SHIFT: u32 = 40;
+
+pub fn corpus(id: u64) -> usize {
+    (id >> CORPUS_SHIFT) as usize
+}
+
+// amount of cleartexts in each corpus
+pub fn corpus_sizes(lines: &HashMap<u64, Vec<u8>>) -> Vec<usize> {
+    let mut sizes = Vec::new();
+    for id in lines.keys() {
+        let c = corpus(*id);
+        if sizes.len() <= c {
+            sizes.resize(c + 1, 0);
+        }
+        sizes[c] += 1;
+    }
+    sizes
+}
+
 pub fn process_line(out: &mut CleartextMap, nth: u64, line: &[u8], minsize: usize) -> usize {
     let ln = line.len();
     let mut inserted = 0;
@@ -34,48 +55,203 @@ pub fn process_line(out: &mut CleartextMap, nth: u64, line: &[u8], minsize: usiz
     inserted
 }
 
-// returns a map with all the fragments, and a hashset with all the lines
-pub fn process(
-    preallocate: bool,
+// approximate memory cost of an inserted fragment, derived from the structure sizes: its
+// CleartextInfo, and a map entry with the spare capacity of the table, as if no fragment was
+// shared between cleartexts (short ones often are), and ignoring the prefixes and suffixes longer
+// than 16 characters
+type Entry<V> = (SmallVec<[u8; 16]>, V);
+pub const FRAGMENT_COST: u64 = (std::mem::size_of::<CleartextInfo>()
+    + std::mem::size_of::<Entry<Vec<CleartextInfo>>>() * 8 / 7
+    + 1) as u64;
+
+// in the same way, a fragment containing uppercase letters in the case folded index
+const FOLDED_COST: u64 = (std::mem::size_of::<SmallVec<[u8; 16]>>()
+    + std::mem::size_of::<Entry<Vec<SmallVec<[u8; 16]>>>>() * 8 / 7
+    + 1) as u64;
+
+// the optional indices built next to the fragment index
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExtraIndices {
+    pub casefold: bool,
+    pub edits: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryStrategy {
+    Refuse,
+    Minsize,
+    Subsample,
+}
+
+impl std::str::FromStr for MemoryStrategy {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "refuse" => Ok(MemoryStrategy::Refuse),
+            "minsize" => Ok(MemoryStrategy::Minsize),
+            "subsample" => Ok(MemoryStrategy::Subsample),
+            _ => Err("Accepted strategies are refuse, minsize and subsample"),
+        }
+    }
+}
+
+// reads the cleartexts of a corpus, skipping those that are too short or already in the wordlist
+pub fn read(
     path: &Path,
+    corpus: usize,
     minsize: usize,
     known: &HashSet<&Vec<u8>>,
-) -> io::Result<(CleartextMap, HashMap<u64, Vec<u8>>)> {
+) -> io::Result<HashMap<u64, Vec<u8>>> {
     let mut idx = HashMap::new();
 
     let file = File::open(path)?;
     let rdr = io::BufReader::new(file);
-    let mut i = 0;
-    let mut inserted = 0;
-    let mut expected_size = 0;
+    let mut i = (corpus as u64) << CORPUS_SHIFT;
 
     for rline in rdr.split(b'\n') {
         let line = rline?;
-        let llen = line.len();
-        if llen < minsize || known.contains(&line) {
+        if line.len() < minsize || known.contains(&line) {
             continue;
         }
         idx.insert(i, line);
         i += 1;
-        let tta = 1 + llen - minsize;
-        expected_size += (tta + 1) * tta / 2;
     }
+    Ok(idx)
+}
+
+fn line_fragments(line: &[u8], minsize: usize) -> usize {
+    if line.len() < minsize {
+        return 0;
+    }
+    let tta = 1 + line.len() - minsize;
+    (tta + 1) * tta / 2
+}
+
+fn line_cost(line: &[u8], minsize: usize, extra: ExtraIndices) -> u64 {
+    let fragments = line_fragments(line, minsize) as u64;
+    let mut cost = fragments * FRAGMENT_COST + line.len() as u64 + 48;
+    if extra.casefold && line.iter().any(|c| c.is_ascii_uppercase()) {
+        cost += fragments * FOLDED_COST;
+    }
+    if extra.edits {
+        cost += edits::line_cost(line, minsize);
+    }
+    cost
+}
+
+// amount of fragments that will be inserted in the index
+pub fn fragments_count(lines: &HashMap<u64, Vec<u8>>, minsize: usize) -> usize {
+    lines.values().map(|l| line_fragments(l, minsize)).sum()
+}
+
+pub fn estimate_memory(lines: &HashMap<u64, Vec<u8>>, minsize: usize, extra: ExtraIndices) -> u64 {
+    lines.values().map(|l| line_cost(l, minsize, extra)).sum()
+}
+
+// makes sure the index will fit in max_memory bytes, by raising minsize or subsampling the lines,
+// depending on the strategy
+// the smallest fraction of the cleartexts kept when subsampling
+const MIN_SUBSAMPLE: f64 = 0.01;
 
+pub fn fit_memory(
+    lines: &mut HashMap<u64, Vec<u8>>,
+    minsize: &mut usize,
+    max_memory: u64,
+    strategy: MemoryStrategy,
+    extra: ExtraIndices,
+) -> Result<(), String> {
+    let estimated = estimate_memory(lines, *minsize, extra);
+    if estimated <= max_memory {
+        return Ok(());
+    }
+    let mb = |x: u64| x / (1024 * 1024);
+    match strategy {
+        MemoryStrategy::Refuse => Err(format!(
+            "the fragment index would use about {}MB, more than the {}MB limit",
+            mb(estimated),
+            mb(max_memory)
+        )),
+        MemoryStrategy::Minsize => {
+            let maxlen = lines.values().map(|l| l.len()).max().unwrap_or(0);
+            let mut newsize = *minsize;
+            while estimate_memory(lines, newsize, extra) > max_memory {
+                newsize += 1;
+                if newsize > maxlen {
+                    return Err(format!(
+                        "the cleartexts do not fit in the {}MB limit, whatever the minimum size",
+                        mb(max_memory)
+                    ));
+                }
+            }
+            eprintln!(
+                "raising the minimum fragment size from {} to {} to fit in {}MB",
+                minsize,
+                newsize,
+                mb(max_memory)
+            );
+            *minsize = newsize;
+            lines.retain(|_, l| l.len() >= newsize);
+            Ok(())
+        }
+        MemoryStrategy::Subsample => {
+            use rand::{Rng, SeedableRng};
+            let mut ids: Vec<u64> = lines.keys().cloned().collect();
+            ids.sort_unstable();
+            let initial = ids.len();
+            let mut ratio = max_memory as f64 / estimated as f64;
+            loop {
+                if ratio < MIN_SUBSAMPLE {
+                    return Err(format!(
+                        "the cleartexts do not fit in the {}MB limit, even when keeping {:.0}% of them",
+                        mb(max_memory),
+                        MIN_SUBSAMPLE * 100.0
+                    ));
+                }
+                // a fixed seed, so that runs can be resumed
+                let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+                let keep: HashSet<u64> = ids
+                    .iter()
+                    .filter(|_| rng.gen::<f64>() < ratio)
+                    .cloned()
+                    .collect();
+                let cost: u64 = keep
+                    .iter()
+                    .map(|k| line_cost(&lines[k], *minsize, extra))
+                    .sum();
+                if cost <= max_memory && !keep.is_empty() {
+                    lines.retain(|k, _| keep.contains(k));
+                    eprintln!(
+                        "subsampling the cleartexts, keeping {}/{} to fit in {}MB",
+                        lines.len(),
+                        initial,
+                        mb(max_memory)
+                    );
+                    return Ok(());
+                }
+                ratio *= 0.95;
+            }
+        }
+    }
+}
+
+// builds the fragment index
+pub fn index(preallocate: bool, lines: &HashMap<u64, Vec<u8>>, minsize: usize) -> CleartextMap {
     // TODO : there is a saturation of low length hashes that can be computed statistically
     // this will reserve way too much space here, but it is not too bad, as this is nothing
     // compared to what's inside the map ...
 
     let mut out = if preallocate {
-        HashMap::with_capacity(expected_size * 7 / 10)
+        HashMap::with_capacity(fragments_count(lines, minsize) * 7 / 10)
     } else {
         HashMap::new()
     };
-    let progress = ProgressBar::new(i);
+    let progress = ProgressBar::new(lines.len() as u64);
     progress.set_style(indicatif::ProgressStyle::default_bar().template(
         "[ETA: {eta_precise}] {bar:60.cyan/blue} {pos}/{len} - {msg} fragments inserted",
     ));
-    i = 0;
-    for (k, line) in &idx {
+    let mut i = 0;
+    let mut inserted = 0;
+    for (k, line) in lines {
         inserted += process_line(&mut out, *k, line, minsize);
         i += 1;
         if i % 2000 == 0 {
@@ -85,7 +261,7 @@ pub fn process(
     }
     progress.finish();
 
-    Ok((out, idx))
+    out
 }
 
 #[cfg(test)]
@@ -95,7 +271,7 @@ mod tests {
     #[test]
     fn test1() {
         let mut out = HashMap::new();
-        let _inserted = process_line(&mut out, 0, &"ABCDEF".as_bytes().to_vec(), 3);
+        let _inserted = process_line(&mut out, 0, "ABCDEF".as_bytes(), 3);
         let expected: &[(&str, (&str, &str))] = &[
             ("ABCDEF", ("", "")),
             ("ABCDE", ("", "F")),
@@ -110,11 +286,89 @@ mod tests {
         ];
         for (k, _tpl) in expected {
             let kv: SmallVec<[u8; 16]> = k.as_bytes().to_smallvec();
-            match out.get(&kv) {
-                None => panic!("Could not find match {}", k),
-                Some(_) => {}
+            if !out.contains_key(&kv) {
+                panic!("Could not find match {}", k)
             }
         }
         assert_eq!(expected.len(), out.len());
     }
+
+    #[test]
+    fn fit() {
+        let mut lines = HashMap::new();
+        for i in 0..1000 {
+            lines.insert(i, format!("password{}", i).into_bytes());
+        }
+        let mut minsize = 4;
+        let extra = ExtraIndices::default();
+        let estimated = estimate_memory(&lines, minsize, extra);
+        assert!(fit_memory(
+            &mut lines,
+            &mut minsize,
+            estimated / 2,
+            MemoryStrategy::Refuse,
+            extra
+        )
+        .is_err());
+
+        let mut raised = lines.clone();
+        fit_memory(
+            &mut raised,
+            &mut minsize,
+            estimated / 2,
+            MemoryStrategy::Minsize,
+            extra,
+        )
+        .unwrap();
+        assert!(minsize > 4);
+        assert!(estimate_memory(&raised, minsize, extra) <= estimated / 2);
+
+        let mut minsize = 4;
+        let mut sampled = lines.clone();
+        fit_memory(
+            &mut sampled,
+            &mut minsize,
+            estimated / 2,
+            MemoryStrategy::Subsample,
+            extra,
+        )
+        .unwrap();
+        assert_eq!(minsize, 4);
+        assert!(sampled.len() < lines.len());
+        assert!(estimate_memory(&sampled, minsize, extra) <= estimated / 2);
+        // the subsample would be empty
+        let mut sampled = lines.clone();
+        assert!(fit_memory(
+            &mut sampled,
+            &mut minsize,
+            estimated / 2000,
+            MemoryStrategy::Subsample,
+            extra
+        )
+        .is_err());
+        assert_eq!(sampled.len(), lines.len());
+
+        // the case folded index only costs memory for the lines with uppercase letters
+        let casefold = ExtraIndices {
+            casefold: true,
+            ..extra
+        };
+        assert_eq!(estimate_memory(&lines, 4, casefold), estimated);
+        lines.insert(1000, b"Password".to_vec());
+        assert!(
+            estimate_memory(&lines, 4, casefold)
+                > estimate_memory(&lines, 4, extra) + 15 * FOLDED_COST - 1
+        );
+
+        // the edit index costs memory for every line
+        let edits = ExtraIndices {
+            edits: true,
+            ..extra
+        };
+        assert_eq!(
+            estimate_memory(&lines, 4, edits),
+            estimate_memory(&lines, 4, extra)
+                + lines.values().map(|l| edits::line_cost(l, 4)).sum::<u64>()
+        );
+    }
 }
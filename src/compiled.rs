@@ -0,0 +1,664 @@
+use crate::rules::{self, CharSelector, CommandRule, Numerical, Rule};
+
+// Rule chains are compiled once into a list of simple operations, that are then run on reusable
+// buffers, so that evaluating a rule on a word does not allocate. The results are the same as
+// rules::mutate.
+
+// a numerical parameter, resolved when the chain is compiled or, for those depending on the
+// length of the word, when it is run
+#[derive(Clone, Copy)]
+enum Arg {
+    Const(u8),
+    WordLen,
+    WordLastCharPos,
+}
+
+fn compile_arg(n: &Numerical) -> Arg {
+    use Numerical::*;
+    match n {
+        Val(x) => Arg::Const(*x),
+        MinLen | MinLenMinus1 => Arg::Const(0),
+        MinLenPlus1 => Arg::Const(1),
+        MaxLen => Arg::Const(254),
+        MaxLenMinus1 => Arg::Const(253),
+        MaxLenPlus1 | Infinite => Arg::Const(255),
+        // user variables are never assigned, and nothing is ever found
+        SavedLen(_) | LastFound => Arg::Const(0),
+        WordLen => Arg::WordLen,
+        WordLastCharPos => Arg::WordLastCharPos,
+    }
+}
+
+type Table = Box<[u8; 256]>;
+type Class = Box<[bool; 256]>;
+
+enum Op {
+    Noop,
+    // all the rules converting each character independently
+    Map(Table),
+    MapAt(Arg, Table),
+    Reverse,
+    Duplicate,
+    Reflect,
+    RotLeft,
+    RotRight,
+    Append(u8),
+    Prefix(u8),
+    InsertString(Arg, Vec<u8>),
+    Truncate(Arg),
+    DeleteFirst,
+    DeleteLast,
+    DeleteAt(Arg),
+    Extract(Arg, Arg),
+    InsertChar(Arg, u8),
+    Overstrike(Arg, u8),
+    Memorize,
+    ExtractInsert(Arg, Arg, Arg),
+    PurgeAll(Class),
+    DupWordNTimes(Arg),
+    SwapFirstTwo,
+    SwapLastTwo,
+    Swap(Arg, Arg),
+    Increment(Arg),
+    Decrement(Arg),
+    AppendMemory,
+    PrependMemory,
+    DupeFirstChar(Arg),
+    DupeLastChar(Arg),
+    DupeAllChar,
+    BitshiftLeft(Arg),
+    BitshiftRight(Arg),
+    ReplaceWithNext(Arg),
+    ReplaceWithPrior(Arg),
+    DupFirstString(Arg),
+    DupLastString(Arg),
+    OmitRange(Arg, Arg),
+    Fail,
+    Pluralize,
+    PastTense,
+    Genitive,
+    TitleCase(Class, Table),
+}
+
+pub struct Program {
+    ops: Vec<Op>,
+    // when false, the original word does not need to be saved
+    uses_memory: bool,
+    // chains with reject rules are not compiled
+    interpreted: Option<Vec<Rule>>,
+}
+
+// conversion table where unconverted characters are left as is
+fn full_table(tbl: &[u8; 256]) -> Table {
+    let mut o = Box::new([0; 256]);
+    for (i, x) in o.iter_mut().enumerate() {
+        *x = if tbl[i] != 0 { tbl[i] } else { i as u8 };
+    }
+    o
+}
+
+fn table_from<F: Fn(u8) -> u8>(f: F) -> Table {
+    let mut o = Box::new([0; 256]);
+    for (i, x) in o.iter_mut().enumerate() {
+        *x = f(i as u8);
+    }
+    o
+}
+
+fn class_table(cl: &CharSelector) -> Class {
+    let mut o = Box::new([false; 256]);
+    for (i, x) in o.iter_mut().enumerate() {
+        *x = rules::in_class(i as u8, cl);
+    }
+    o
+}
+
+fn compile_command(cmd: &CommandRule, ops: &mut Vec<Op>) {
+    use CommandRule::*;
+    let convs = &super::CONVS;
+    let a = compile_arg;
+    let op = match cmd {
+        Noop => Op::Noop,
+        ToLower => Op::Map(table_from(|c| c.to_ascii_lowercase())),
+        ToUpper => Op::Map(table_from(|c| c.to_ascii_uppercase())),
+        Capitalize => {
+            ops.push(Op::Map(table_from(|c| c.to_ascii_lowercase())));
+            Op::MapAt(Arg::Const(0), table_from(|c| c.to_ascii_uppercase()))
+        }
+        InvertCapitalize => {
+            ops.push(Op::Map(table_from(|c| c.to_ascii_uppercase())));
+            Op::MapAt(Arg::Const(0), table_from(|c| c.to_ascii_lowercase()))
+        }
+        ToggleAll => Op::Map(full_table(&convs.cinvert)),
+        ShiftAll => Op::Map(full_table(&convs.cshift)),
+        LowerVowelsUpperConsonants => Op::Map(full_table(&convs.cvowels)),
+        ShiftAllKeyboardRight => Op::Map(full_table(&convs.cright)),
+        ShiftAllKeyboardLeft => Op::Map(full_table(&convs.cleft)),
+        ToggleCase(p) => Op::MapAt(a(p), full_table(&convs.cinvert)),
+        ToggleShift(p) => Op::MapAt(a(p), full_table(&convs.cshift)),
+        Reverse => Op::Reverse,
+        Duplicate => Op::Duplicate,
+        Reflect => Op::Reflect,
+        RotLeft => Op::RotLeft,
+        RotRight => Op::RotRight,
+        Append(c) => Op::Append(*c),
+        Prefix(c) => Op::Prefix(*c),
+        InsertString(n, s) => Op::InsertString(a(n), s.clone()),
+        Truncate(p) => Op::Truncate(a(p)),
+        DeleteFirst => Op::DeleteFirst,
+        DeleteLast => Op::DeleteLast,
+        DeleteAt(p) => Op::DeleteAt(a(p)),
+        Extract(p, l) => Op::Extract(a(p), a(l)),
+        InsertChar(p, c) => Op::InsertChar(a(p), *c),
+        Overstrike(p, c) => Op::Overstrike(a(p), *c),
+        Memorize => Op::Memorize,
+        ExtractInsert(pe, l, pi) => Op::ExtractInsert(a(pe), a(l), a(pi)),
+        ReplaceAll(cl, cr) => {
+            let cls = class_table(cl);
+            Op::Map(table_from(|c| if cls[c as usize] { *cr } else { c }))
+        }
+        PurgeAll(cl) => Op::PurgeAll(class_table(cl)),
+        DupWordNTimes(n) => Op::DupWordNTimes(a(n)),
+        SwapFirstTwo => Op::SwapFirstTwo,
+        SwapLastTwo => Op::SwapLastTwo,
+        Swap(p1, p2) => Op::Swap(a(p1), a(p2)),
+        Increment(p) => Op::Increment(a(p)),
+        Decrement(p) => Op::Decrement(a(p)),
+        AppendMemory => Op::AppendMemory,
+        PrependMemory => Op::PrependMemory,
+        DupeFirstChar(n) => Op::DupeFirstChar(a(n)),
+        DupeLastChar(n) => Op::DupeLastChar(a(n)),
+        DupeAllChar => Op::DupeAllChar,
+        BitshiftLeft(p) => Op::BitshiftLeft(a(p)),
+        BitshiftRight(p) => Op::BitshiftRight(a(p)),
+        ReplaceWithNext(p) => Op::ReplaceWithNext(a(p)),
+        ReplaceWithPrior(p) => Op::ReplaceWithPrior(a(p)),
+        DupFirstString(n) => Op::DupFirstString(a(n)),
+        DupLastString(n) => Op::DupLastString(a(n)),
+        OmitRange(p, l) => Op::OmitRange(a(p), a(l)),
+        MemoryAssign(_, _, _) => Op::Fail,
+        Pluralize => Op::Pluralize,
+        PastTense => Op::PastTense,
+        Genitive => Op::Genitive,
+        TitleCase(cl) => Op::TitleCase(class_table(cl), full_table(&convs.cinvert)),
+    };
+    // consecutive conversions are merged, as they never change the length of the word (so the
+    // word can not become empty between them)
+    if let (Op::Map(next), Some(Op::Map(prev))) = (&op, ops.last_mut()) {
+        for x in prev.iter_mut() {
+            *x = next[*x as usize];
+        }
+        return;
+    }
+    ops.push(op);
+}
+
+pub fn compile(rules: &[Rule]) -> Program {
+    let mut ops = Vec::new();
+    for r in rules {
+        match r {
+            Rule::Reject(_) => {
+                return Program {
+                    ops: Vec::new(),
+                    uses_memory: false,
+                    interpreted: Some(rules.to_vec()),
+                }
+            }
+            Rule::Command(cmd) => compile_command(cmd, &mut ops),
+        }
+    }
+    let uses_memory = ops.iter().any(|o| {
+        matches!(
+            o,
+            Op::Memorize | Op::ExtractInsert(_, _, _) | Op::AppendMemory | Op::PrependMemory
+        )
+    });
+    Program {
+        ops,
+        uses_memory,
+        interpreted: None,
+    }
+}
+
+fn is_vowel_no_y(x: u8) -> bool {
+    x == b'a' || x == b'e' || x == b'i' || x == b'o' || x == b'u'
+}
+
+fn is_bgp(x: u8) -> bool {
+    x == b'b' || x == b'g' || x == b'p'
+}
+
+// reusable buffers for running compiled programs
+#[derive(Default)]
+pub struct Executor {
+    cur: Vec<u8>,
+    memory: Vec<u8>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor::default()
+    }
+
+    // same as rules::mutate, the result is valid until the next run
+    pub fn run(&mut self, program: &Program, word: &[u8]) -> Option<&[u8]> {
+        if let Some(rules) = &program.interpreted {
+            self.cur = rules::mutate(word, rules)?;
+            return Some(&self.cur);
+        }
+        let savedlen = word.len() as u8;
+        let arg = |a: &Arg| match a {
+            Arg::Const(x) => *x as usize,
+            Arg::WordLen => savedlen as usize,
+            Arg::WordLastCharPos => savedlen.saturating_sub(1) as usize,
+        };
+        let cur = &mut self.cur;
+        let memory = &mut self.memory;
+        cur.clear();
+        cur.extend_from_slice(word);
+        if program.uses_memory {
+            memory.clear();
+            memory.extend_from_slice(word);
+        }
+        for op in &program.ops {
+            let curlength = cur.len();
+            if curlength == 0 {
+                return None;
+            }
+            match op {
+                Op::Noop => (),
+                Op::Map(tbl) => {
+                    for c in cur.iter_mut() {
+                        *c = tbl[*c as usize];
+                    }
+                }
+                Op::MapAt(p, tbl) => {
+                    if let Some(c) = cur.get_mut(arg(p)) {
+                        *c = tbl[*c as usize];
+                    }
+                }
+                Op::Reverse => cur.reverse(),
+                Op::Duplicate => cur.extend_from_within(..),
+                Op::Reflect => {
+                    cur.extend_from_within(..);
+                    cur[curlength..].reverse();
+                }
+                Op::RotLeft => cur.rotate_left(1),
+                Op::RotRight => cur.rotate_right(1),
+                Op::Append(c) => cur.push(*c),
+                Op::Prefix(c) => cur.insert(0, *c),
+                Op::InsertString(n, s) => {
+                    let pos = arg(n).min(cur.len());
+                    cur.splice(pos..pos, s.iter().cloned());
+                }
+                Op::Truncate(p) => cur.truncate(arg(p)),
+                Op::DeleteFirst => {
+                    let _ = cur.remove(0);
+                }
+                Op::DeleteLast => {
+                    let _ = cur.pop();
+                }
+                Op::DeleteAt(p) => {
+                    let pos = arg(p);
+                    if curlength <= pos {
+                        return None;
+                    }
+                    let _ = cur.remove(pos);
+                }
+                Op::Extract(p, l) => {
+                    let pos = arg(p);
+                    let len = arg(l);
+                    if pos >= curlength || pos + len >= curlength - 1 {
+                        return None;
+                    }
+                    cur.truncate(pos + len);
+                    cur.drain(..pos);
+                }
+                Op::InsertChar(p, c) => {
+                    let pos = arg(p);
+                    if curlength <= pos {
+                        return None;
+                    }
+                    cur.insert(pos, *c);
+                }
+                Op::Overstrike(p, c) => {
+                    let pos = arg(p);
+                    if curlength <= pos {
+                        return None;
+                    }
+                    cur[pos] = *c;
+                }
+                Op::Memorize => {
+                    memory.clear();
+                    memory.extend_from_slice(cur);
+                }
+                Op::ExtractInsert(pe, l, pi) => {
+                    let pe_ = arg(pe);
+                    let l_ = arg(l);
+                    let pi_ = arg(pi);
+                    if let Some(to_insert) = memory.get(pe_..(pe_ + l_)) {
+                        cur.splice(pi_..pi_, to_insert.iter().cloned());
+                    }
+                }
+                Op::PurgeAll(cls) => cur.retain(|&c| !cls[c as usize]),
+                Op::DupWordNTimes(n) => {
+                    for _ in 0..arg(n) {
+                        cur.extend_from_within(..curlength);
+                    }
+                }
+                Op::SwapFirstTwo => {
+                    if curlength < 2 {
+                        return None;
+                    }
+                    cur.swap(0, 1);
+                }
+                Op::SwapLastTwo => {
+                    if curlength < 2 {
+                        return None;
+                    }
+                    cur.swap(curlength - 2, curlength - 1);
+                }
+                Op::Swap(p1, p2) => {
+                    let p1_ = arg(p1);
+                    let p2_ = arg(p2);
+                    if curlength <= p1_ || curlength <= p2_ {
+                        return None;
+                    }
+                    cur.swap(p1_, p2_);
+                }
+                Op::Increment(p) => {
+                    let pos = arg(p);
+                    if curlength > pos {
+                        cur[pos] += 1;
+                    }
+                }
+                Op::Decrement(p) => {
+                    let pos = arg(p);
+                    if curlength <= pos {
+                        return None;
+                    }
+                    cur[pos] -= 1;
+                }
+                Op::AppendMemory => cur.extend_from_slice(memory),
+                Op::PrependMemory => {
+                    cur.splice(0..0, memory.iter().cloned());
+                }
+                Op::DupeFirstChar(n) => {
+                    let c0 = cur[0];
+                    cur.splice(0..0, std::iter::repeat_n(c0, arg(n)));
+                }
+                Op::DupeLastChar(n) => {
+                    let lst = cur[curlength - 1];
+                    cur.extend(std::iter::repeat_n(lst, arg(n)));
+                }
+                Op::DupeAllChar => {
+                    cur.resize(curlength * 2, 0);
+                    for i in (0..curlength).rev() {
+                        cur[2 * i + 1] = cur[i];
+                        cur[2 * i] = cur[i];
+                    }
+                }
+                Op::BitshiftLeft(p) => {
+                    let pos = arg(p);
+                    if curlength <= pos {
+                        return None;
+                    }
+                    cur[pos] <<= 1;
+                }
+                Op::BitshiftRight(p) => {
+                    let pos = arg(p);
+                    if curlength <= pos {
+                        return None;
+                    }
+                    cur[pos] >>= 1;
+                }
+                Op::ReplaceWithNext(p) => {
+                    let pos = arg(p);
+                    if curlength <= pos + 1 {
+                        return None;
+                    }
+                    cur[pos] = cur[pos + 1];
+                }
+                Op::ReplaceWithPrior(p) => {
+                    let pos = arg(p);
+                    if curlength <= 1 || pos == 0 || pos >= curlength {
+                        return None;
+                    }
+                    cur[pos] = cur[pos - 1];
+                }
+                Op::DupFirstString(n) => {
+                    let sz = arg(n);
+                    if sz >= curlength {
+                        return None;
+                    }
+                    cur.extend_from_within(..sz);
+                    cur.rotate_right(sz);
+                }
+                Op::DupLastString(n) => {
+                    let sz = arg(n);
+                    if curlength < sz {
+                        return None;
+                    }
+                    cur.extend_from_within(curlength - sz..);
+                }
+                Op::OmitRange(p, l) => {
+                    let pos = arg(p);
+                    let ln = arg(l);
+                    if pos >= curlength {
+                        return None;
+                    }
+                    if pos + ln >= curlength {
+                        cur.truncate(pos);
+                    } else {
+                        cur.drain(pos..pos + ln);
+                    }
+                }
+                Op::Fail => return None,
+                Op::Pluralize => {
+                    if curlength < 2 {
+                        return None;
+                    }
+                    let last_letter = cur[curlength - 1];
+                    let prev_letter = cur[curlength - 2];
+                    if last_letter == b's'
+                        || last_letter == b'x'
+                        || last_letter == b'z'
+                        || (last_letter == b'h' && (prev_letter == b'c' || prev_letter == b's'))
+                    {
+                        cur.push(b'e');
+                    } else if last_letter == b'f' && prev_letter != b'f' {
+                        cur[curlength - 1] = b'v';
+                        cur.push(b'e');
+                    } else if last_letter == b'e' && prev_letter == b'f' {
+                        cur[curlength - 2] = b'v';
+                        cur[curlength - 1] = b'e';
+                    } else if last_letter == b'y' && !is_vowel_no_y(prev_letter) {
+                        cur[curlength - 1] = b'i';
+                        cur.push(b'e');
+                    }
+                    cur.push(b's');
+                }
+                Op::PastTense => {
+                    if curlength < 3 {
+                        return None;
+                    }
+                    let raw_last_letter = cur[curlength - 1];
+                    let mut last_letter = raw_last_letter;
+                    let prev_letter = cur[curlength - 2];
+                    if last_letter != b'd' || prev_letter != b'e' {
+                        if last_letter == b'y' {
+                            last_letter = b'i';
+                            cur[curlength - 1] = last_letter;
+                        } else if is_bgp(last_letter) && !is_bgp(prev_letter) {
+                            cur.push(raw_last_letter);
+                        }
+                        if last_letter != b'e' {
+                            cur.push(b'e');
+                        }
+                        cur.push(b'd');
+                    }
+                }
+                Op::Genitive => {
+                    if curlength < 3 {
+                        return None;
+                    }
+                    let last_letter = cur[curlength - 1];
+                    let prev_letter = cur[curlength - 2];
+                    let pprev_letter = cur[curlength - 3];
+                    if last_letter != b'g' || prev_letter != b'n' || pprev_letter != b'i' {
+                        if is_vowel_no_y(last_letter) {
+                            cur[curlength - 1] = b'i';
+                        } else {
+                            if is_bgp(last_letter) && !is_bgp(prev_letter) {
+                                cur.push(last_letter);
+                            }
+                            cur.push(b'i');
+                        }
+                        cur.push(b'n');
+                        cur.push(b'g');
+                    }
+                }
+                Op::TitleCase(cls, tbl) => {
+                    let mut title = true;
+                    for c in cur.iter_mut() {
+                        if title {
+                            *c = tbl[*c as usize];
+                        }
+                        title = cls[*c as usize];
+                    }
+                }
+            }
+        }
+        Some(cur)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::CharClass::*;
+    use crate::rules::CharSelector::*;
+    use crate::rules::CommandRule::*;
+    use crate::rules::Numerical::*;
+    use crate::rules::RejectRule::UnlessWordLengthMoreThan;
+    use crate::rules::Rule::*;
+
+    fn words() -> Vec<Vec<u8>> {
+        [
+            "",
+            "a",
+            "ab",
+            "abc",
+            "sky",
+            "fly",
+            "bed",
+            "stop",
+            "play",
+            "leaf",
+            "cliff",
+            "knife",
+            "boxes",
+            "church",
+            "going",
+            "make",
+            "password",
+            "Password1",
+            "P@ssw0rd!",
+            "hello world",
+            "a.b,c d",
+            "ALLCAPS",
+            "MiXeD.cAsE",
+            "0123456789",
+            "qwertyuiopasdfghjklzxcvbnm1234567890",
+        ]
+        .iter()
+        .map(|w| w.as_bytes().to_vec())
+        .collect()
+    }
+
+    fn check(rules: &[Rule], words: &[Vec<u8>], executor: &mut Executor) {
+        let program = compile(rules);
+        for w in words {
+            let expected = rules::mutate(w, rules);
+            let res = executor.run(&program, w).map(|x| x.to_vec());
+            assert_eq!(
+                res,
+                expected,
+                "{:?} on {:?}",
+                rules,
+                String::from_utf8_lossy(w)
+            );
+        }
+    }
+
+    #[test]
+    fn same_as_mutate() {
+        let words = words();
+        let mut executor = Executor::new();
+        for rules in rules::genmutate() {
+            check(&rules, &words, &mut executor);
+        }
+        let chains = vec![
+            vec![Command(Memorize), Command(Reverse), Command(AppendMemory)],
+            vec![Command(DeleteFirst), Command(PrependMemory)],
+            vec![
+                Command(Memorize),
+                Command(ExtractInsert(Val(1), Val(2), Val(0))),
+            ],
+            vec![Command(ExtractInsert(Val(0), WordLen, Val(1)))],
+            vec![
+                Command(ToUpper),
+                Command(ToggleCase(Val(1))),
+                Command(ToLower),
+            ],
+            vec![Command(InvertCapitalize), Command(Append(b'1'))],
+            vec![Command(InsertString(Val(0), b"xy".to_vec()))],
+            vec![
+                Command(Reverse),
+                Command(InsertString(rules::Numerical::Infinite, b"12".to_vec())),
+            ],
+            vec![Command(InsertString(Val(5), b"!!".to_vec()))],
+            vec![Command(DeleteLast), Command(Noop)],
+            vec![Command(DeleteFirst), Command(ToUpper), Command(ToLower)],
+            vec![Command(TitleCase(NoneOf(CCLetters)))],
+            vec![
+                Command(Truncate(SavedLen(rules::UserVar::UVA))),
+                Command(Noop),
+            ],
+            vec![Command(MemoryAssign(rules::UserVar::UVA, Val(0), Val(1)))],
+            vec![Reject(UnlessWordLengthMoreThan(Val(4))), Command(Reverse)],
+        ];
+        for rules in chains {
+            check(&rules, &words, &mut executor);
+        }
+    }
+
+    #[test]
+    fn insert_past_end() {
+        // Az"12" appends, as does an insertion past the end of the word
+        let mut executor = Executor::new();
+        for (rules, word, expected) in [
+            (
+                vec![Command(InsertString(Infinite, b"12".to_vec()))],
+                "pass",
+                "pass12",
+            ),
+            (
+                vec![Command(InsertString(Val(5), b"!!".to_vec()))],
+                "abc",
+                "abc!!",
+            ),
+            (
+                vec![Command(InsertString(Val(2), b"!!".to_vec()))],
+                "abc",
+                "ab!!c",
+            ),
+        ] {
+            let expected = Some(expected.as_bytes().to_vec());
+            assert_eq!(rules::mutate(word.as_bytes(), &rules), expected);
+            let program = compile(&rules);
+            let res = executor.run(&program, word.as_bytes()).map(|x| x.to_vec());
+            assert_eq!(res, expected);
+        }
+    }
+}
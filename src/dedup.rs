@@ -0,0 +1,147 @@
+use crate::compiled;
+use crate::rules::Rule;
+use crate::selection::shorter_rules;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+// Many generated rules behave the same (Truncate(WordLen) and Noop, Swap(n,m) and Swap(m,n), ...),
+// and each of them costs a full pass on the wordlist. Rules giving the same results on a set of
+// probe words are considered equivalent, and only the shortest one is kept.
+
+// words with various lengths, cases and endings, so that the grammar rules and the positional
+// rules get exercised
+static PROBES: &[&str] = &[
+    "a",
+    "B",
+    "1",
+    "ab",
+    "Ab",
+    "a1",
+    "12",
+    "abc",
+    "sky",
+    "boy",
+    "BED",
+    "x2!",
+    "stop",
+    "bush",
+    "leaf",
+    "wife",
+    "play",
+    "Fly1",
+    "ring",
+    "a.b c",
+    "boxes",
+    "cliff",
+    "going",
+    "tried",
+    "Hello",
+    "church",
+    "making",
+    "wanted",
+    "monkey",
+    "123456",
+    "passwd",
+    "azerty",
+    "P@ssw0rd",
+    "password",
+    "qwertyui",
+    "dragon12",
+    "sunshine",
+    "iloveyou",
+    "Football",
+    "hello world",
+    "MiXeD.CaSe",
+    "0123456789",
+    "abcdefghijklmnop",
+    "The_Quick-Brown;Fox",
+    "zzzzzzzzzzzzzzzzzzzzzzzz",
+];
+
+// the fixed probes, and a sample of at most max_sampled words of the wordlist
+pub fn probe_words(wordlist: &[Vec<u8>], max_sampled: usize) -> Vec<Vec<u8>> {
+    let mut o: Vec<Vec<u8>> = PROBES.iter().map(|w| w.as_bytes().to_vec()).collect();
+    if let Some(step) = wordlist.len().checked_div(max_sampled) {
+        o.extend(
+            wordlist
+                .iter()
+                .step_by(step.max(1))
+                .take(max_sampled)
+                .filter(|w| !w.is_empty())
+                .cloned(),
+        );
+    }
+    o
+}
+
+fn fingerprint(rules: &[Rule], probes: &[Vec<u8>], executor: &mut compiled::Executor) -> u64 {
+    let program = compiled::compile(rules);
+    let mut hasher = DefaultHasher::new();
+    for w in probes {
+        executor.run(&program, w).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+// keeps the shortest of each set of equivalent rules, in the original order, and returns the
+// amount of removed rules
+pub fn dedup(allrules: Vec<Vec<Rule>>, probes: &[Vec<u8>]) -> (Vec<Vec<Rule>>, usize) {
+    let mut executor = compiled::Executor::new();
+    // fingerprint -> index of the best rule
+    let mut best: HashMap<u64, usize> = HashMap::new();
+    for (i, rules) in allrules.iter().enumerate() {
+        let fp = fingerprint(rules, probes, &mut executor);
+        best.entry(fp)
+            .and_modify(|b| {
+                if shorter_rules(rules, &allrules[*b]) {
+                    *b = i
+                }
+            })
+            .or_insert(i);
+    }
+    let mut keep = vec![false; allrules.len()];
+    for i in best.values() {
+        keep[*i] = true;
+    }
+    let removed = allrules.len() - best.len();
+    let out = allrules
+        .into_iter()
+        .zip(keep)
+        .filter(|(_, k)| *k)
+        .map(|(r, _)| r)
+        .collect();
+    (out, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::CommandRule::*;
+    use crate::rules::Numerical::*;
+    use crate::rules::Rule::*;
+
+    #[test]
+    fn equivalent() {
+        let allrules = vec![
+            vec![Command(Truncate(WordLen))],
+            vec![Command(Swap(Val(2), Val(1)))],
+            vec![Command(Reverse)],
+            vec![Command(Noop)],
+            vec![Command(Swap(Val(1), Val(2)))],
+            vec![Command(Extract(Val(0), WordLen))],
+            vec![Command(ToggleCase(Val(0))), Command(ToggleCase(Val(0)))],
+        ];
+        let (res, removed) = dedup(allrules, &probe_words(&[], 0));
+        assert_eq!(removed, 3);
+        assert_eq!(
+            res,
+            vec![
+                vec![Command(Reverse)],
+                vec![Command(Noop)],
+                vec![Command(Swap(Val(1), Val(2)))],
+                vec![Command(Extract(Val(0), WordLen))],
+            ]
+        );
+    }
+}
@@ -3,8 +3,11 @@ use crate::rules::mutate;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::path::PathBuf;
 use structopt::StructOpt;
 
+mod policy;
+mod rulefile;
 mod rules;
 
 lazy_static::lazy_static! {
@@ -39,6 +42,12 @@ struct Opts {
     /// Dictionary to apply rules on
     #[structopt(long = "dict")]
     dict: Option<String>,
+    /// Rule file to apply instead of the generated rules, in the hashcat syntax with --hashcat (apply mode)
+    #[structopt(long = "rules", parse(from_os_str))]
+    rules: Option<PathBuf>,
+    /// Only output the candidates complying with the password policy, such as minlen=8,classes=3 (apply mode)
+    #[structopt(long = "policy")]
+    policy: Option<policy::Policy>,
 }
 
 fn main() {
@@ -77,17 +86,31 @@ fn main() {
         }
         Mode::ApplyRules => {
             let rdict = opt.dict.expect("Missing --dict parameter");
+            let policy = opt.policy;
             let file = File::open(rdict).unwrap();
             let reader = BufReader::new(file);
             let rlns: Result<Vec<String>, _> = reader.lines().collect();
             let lns = rlns.unwrap();
-            for r in genmutate()
-                .into_iter()
-                .filter(|rs| rs.iter().all(rules::hashcat_rule))
-            {
+            let ruleset: Vec<Vec<rules::Rule>> = match &opt.rules {
+                Some(path) => {
+                    let file = rulefile::read_rules(path, opt.hashcat, !opt.hashcat)
+                        .unwrap_or_else(|e| panic!("could not read rules: {}", e));
+                    for e in &file.errors {
+                        eprintln!("{} (skipped)", e);
+                    }
+                    file.rules.into_iter().map(|(_, rs)| rs).collect()
+                }
+                None => genmutate()
+                    .into_iter()
+                    .filter(|rs| rs.iter().all(rules::hashcat_rule))
+                    .collect(),
+            };
+            for r in ruleset {
                 // println!("// {:?}", r);
                 for l in &lns {
-                    if let Some(o) = mutate(l.as_bytes(), &r) {
+                    if let Some(o) = mutate(l.as_bytes(), &r)
+                        .filter(|o| policy.as_ref().is_none_or(|p| p.accepts(o)))
+                    {
                         std::io::stdout().write_all(&o).unwrap();
                         std::io::stdout().write_all(b"\n").unwrap();
                     }
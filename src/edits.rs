@@ -0,0 +1,146 @@
+use crate::cleartexts::CleartextMap;
+use crate::matcher::{record_matches, MAX_POSITION};
+use crate::rules::{CommandRule, Numerical, Rule};
+use std::collections::{BTreeSet, HashMap};
+
+// Index of the fragments with one character replaced by a wildcard, used to find the fragments
+// that differ from a mutated word by a single overstruck character, so that the o rules for all
+// characters and positions are learned in a single pass, along with the prefix and suffix of the
+// cleartexts, as for the other rules. The D rules only need the fragment index, by removing each
+// character of the mutated word.
+
+// FNV-1a, hashing several slices as if they were concatenated
+fn hash_parts(parts: &[&[u8]]) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for p in parts {
+        for c in p.iter() {
+            h ^= *c as u64;
+            h = h.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+    h
+}
+
+// the word without its nth character, and the position
+fn hash_hole(word: &[u8], n: usize) -> u64 {
+    hash_parts(&[&[n as u8], &word[..n], &word[n + 1..]])
+}
+
+// approximate memory cost of a holed fragment: a map entry with the spare capacity of the table,
+// and its character
+const HOLE_COST: usize = std::mem::size_of::<(u64, Vec<u8>)>() * 8 / 7 + 2;
+
+// approximate memory cost of the holed fragments of a cleartext, as if no fragment was shared
+pub fn line_cost(line: &[u8], minsize: usize) -> u64 {
+    let holes: usize = (minsize..=line.len())
+        .map(|sz| (line.len() - sz + 1) * sz.min(MAX_POSITION + 1))
+        .sum();
+    (holes * HOLE_COST) as u64
+}
+
+pub struct EditIndex {
+    // hash of the fragment with a missing character -> the characters it can be replaced with
+    holes: HashMap<u64, Vec<u8>>,
+}
+
+impl EditIndex {
+    pub fn new(aclear: &CleartextMap) -> Self {
+        let mut holes: HashMap<u64, Vec<u8>> = HashMap::new();
+        for fragment in aclear.keys() {
+            for n in 0..fragment.len().min(MAX_POSITION + 1) {
+                let chars = holes.entry(hash_hole(fragment, n)).or_default();
+                if !chars.contains(&fragment[n]) {
+                    chars.push(fragment[n]);
+                }
+            }
+        }
+        EditIndex { holes }
+    }
+
+    // records the overstrike and deletion rules turning the mutated word into a fragment, along
+    // with the prefix and suffix of its cleartexts
+    pub fn record(
+        &self,
+        hits: &mut HashMap<Vec<Rule>, BTreeSet<u64>>,
+        rules: &[Rule],
+        mutated: &[u8],
+        aclear: &CleartextMap,
+    ) {
+        let mut record = |cmd: CommandRule, fragment: &[u8]| {
+            if let Some(matches) = aclear.get(fragment) {
+                let mut currule = rules.to_vec();
+                currule.push(Rule::Command(cmd));
+                record_matches(hits, &currule, matches);
+            }
+        };
+        let mut edited = mutated.to_vec();
+        let mut deleted = Vec::with_capacity(mutated.len());
+        for n in 0..mutated.len().min(MAX_POSITION + 1) {
+            if let Some(chars) = self.holes.get(&hash_hole(mutated, n)) {
+                for c in chars.iter().filter(|c| **c != mutated[n]) {
+                    edited[n] = *c;
+                    record(
+                        CommandRule::Overstrike(Numerical::Val(n as u8), *c),
+                        &edited,
+                    );
+                }
+                edited[n] = mutated[n];
+            }
+            deleted.clear();
+            deleted.extend_from_slice(&mutated[..n]);
+            deleted.extend_from_slice(&mutated[n + 1..]);
+            record(CommandRule::DeleteAt(Numerical::Val(n as u8)), &deleted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cleartexts;
+    use crate::rules::CommandRule::*;
+    use crate::rules::Numerical::*;
+    use crate::rules::Rule::Command;
+
+    #[test]
+    fn edits() {
+        let lines = ["passw0rd", "pasword", "passw0rd123", "1pasword", "pazzword"];
+        let mut aclear = HashMap::new();
+        for (i, l) in lines.iter().enumerate() {
+            cleartexts::process_line(&mut aclear, i as u64, l.as_bytes(), 7);
+        }
+        let index = EditIndex::new(&aclear);
+        let mut hits = HashMap::new();
+        index.record(&mut hits, &[Command(Noop)], b"password", &aclear);
+        let mut expected = HashMap::new();
+        let rule = |c: &[CommandRule]| {
+            let mut rs = vec![Command(Noop)];
+            rs.extend(c.iter().cloned().map(Command));
+            rs
+        };
+        let ids = |v: &[u64]| v.iter().cloned().collect::<BTreeSet<u64>>();
+        expected.insert(rule(&[Overstrike(Val(5), b'0')]), ids(&[0]));
+        // edits combined with a prefix or a suffix
+        expected.insert(
+            rule(&[
+                Overstrike(Val(5), b'0'),
+                InsertString(Infinite, b"123".to_vec()),
+            ]),
+            ids(&[2]),
+        );
+        expected.insert(rule(&[DeleteAt(Val(2))]), ids(&[1]));
+        expected.insert(rule(&[DeleteAt(Val(3))]), ids(&[1]));
+        expected.insert(rule(&[DeleteAt(Val(2)), Prefix(b'1')]), ids(&[3]));
+        expected.insert(rule(&[DeleteAt(Val(3)), Prefix(b'1')]), ids(&[3]));
+        assert_eq!(hits, expected);
+        // the rules give the cleartexts
+        for (rs, found) in hits {
+            for id in found {
+                assert_eq!(
+                    crate::rules::mutate(b"password", &rs).unwrap(),
+                    lines[id as usize].as_bytes()
+                );
+            }
+        }
+    }
+}
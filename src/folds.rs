@@ -0,0 +1,114 @@
+use crate::rules::Rule;
+use crate::selection::{self, HitMap, RuleHits, Scoring};
+use std::collections::HashMap;
+
+// The selection is run again on k disjoint slices of the cleartexts, so that the rules only
+// selected on some of them, by chance, can be spotted.
+
+// marginal gain of the rules in each fold, scaled to the whole corpus, 0 when not selected
+pub struct Stability {
+    folds: usize,
+    gains: HashMap<Vec<Rule>, Vec<usize>>,
+}
+
+pub fn stability(hits: &HitMap, folds: usize, cutoff: usize, scoring: &Scoring) -> Stability {
+    let mut gains: HashMap<Vec<Rule>, Vec<usize>> = HashMap::new();
+    let fold_cutoff = cutoff.div_ceil(folds).max(1);
+    for fold in 0..folds {
+        let mut fold_hits: HitMap = hits
+            .iter()
+            .filter_map(|(rules, rh)| {
+                let cracked: Vec<u64> = rh
+                    .cracked
+                    .iter()
+                    .filter(|id| **id % folds as u64 == fold as u64)
+                    .cloned()
+                    .collect();
+                if cracked.len() < fold_cutoff {
+                    return None;
+                }
+                Some((
+                    rules.clone(),
+                    RuleHits {
+                        candidates: rh.candidates,
+                        cracked,
+                    },
+                ))
+            })
+            .collect();
+        selection::greedy(&mut fold_hits, fold_cutoff, scoring, |sel| {
+            gains
+                .entry(sel.rules.clone())
+                .or_insert_with(|| vec![0; folds])[fold] = sel.count * folds;
+            true
+        });
+    }
+    Stability { folds, gains }
+}
+
+impl Stability {
+    // amount of folds selecting the rule, mean and standard deviation of its gain
+    pub fn of(&self, rules: &[Rule]) -> (usize, f64, f64) {
+        let gains = match self.gains.get(rules) {
+            None => return (0, 0.0, 0.0),
+            Some(g) => g,
+        };
+        let n = self.folds as f64;
+        let mean = gains.iter().sum::<usize>() as f64 / n;
+        let var = gains
+            .iter()
+            .map(|g| (*g as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        (gains.iter().filter(|g| **g > 0).count(), mean, var.sqrt())
+    }
+
+    pub fn show(&self, rules: &[Rule]) -> String {
+        let (selected, mean, sd) = self.of(rules);
+        format!(
+            "folds {}/{} - gain {:.1} ± {:.1}",
+            selected, self.folds, mean, sd
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::CommandRule::*;
+    use crate::rules::Rule::Command;
+    use crate::selection::Objective;
+
+    #[test]
+    fn folds() {
+        let mut hits = HashMap::new();
+        // cracks passwords in all folds
+        hits.insert(
+            vec![Command(Append(b'1'))],
+            RuleHits {
+                candidates: 10,
+                cracked: (0..30).collect(),
+            },
+        );
+        // only cracks passwords in the first fold
+        hits.insert(
+            vec![Command(Append(b'2'))],
+            RuleHits {
+                candidates: 10,
+                cracked: vec![30, 33, 36, 39],
+            },
+        );
+        let scoring = Scoring {
+            objective: Objective::Cracks,
+            hash_cost: 1.0,
+            wordlist_len: 10,
+            corpus_sizes: Vec::new(),
+        };
+        let st = stability(&hits, 3, 3, &scoring);
+        assert_eq!(st.of(&[Command(Append(b'1'))]), (3, 30.0, 0.0));
+        let (selected, mean, sd) = st.of(&[Command(Append(b'2'))]);
+        assert_eq!((selected, mean), (1, 4.0));
+        assert!(sd > 5.0);
+        assert_eq!(st.of(&[Command(Append(b'3'))]), (0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::compiled;
+use crate::matcher::MAX_POSITION;
+use crate::rules::{Numerical, RejectRule, Rule};
+use std::collections::HashMap;
+
+// Many rules only crack passwords from words of some lengths, such as '6 or D7. The lengths of
+// the words producing the cracks of a rule give the <N and >N rejects that skip the other words.
+
+pub struct Gate {
+    // accepted lengths of the input words
+    pub min: usize,
+    pub max: usize,
+    pub cracks: usize,
+    pub kept: usize,
+    pub candidates: u64,
+    pub saved: u64,
+    // candidates by word length
+    by_len: Vec<u64>,
+}
+
+// the interval of word lengths saving the most candidates, while losing at most max_loss of the
+// cracked passwords
+pub fn gate(
+    rules: &[Rule],
+    wordlist: &[Vec<u8>],
+    lines: &HashMap<u64, Vec<u8>>,
+    cracked: &[u64],
+    max_loss: f64,
+) -> Gate {
+    let mut ids: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (i, id) in cracked.iter().enumerate() {
+        if let Some(line) = lines.get(id) {
+            ids.entry(line.as_slice()).or_default().push(i);
+        }
+    }
+    // candidates by word length, and word lengths producing each crack
+    let mut by_len: Vec<u64> = Vec::new();
+    let mut crack_lens: Vec<Vec<usize>> = vec![Vec::new(); cracked.len()];
+    let program = compiled::compile(rules);
+    let mut executor = compiled::Executor::new();
+    for word in wordlist {
+        let mutated = match executor.run(&program, word) {
+            Some(m) => m,
+            None => continue,
+        };
+        if by_len.len() <= word.len() {
+            by_len.resize(word.len() + 1, 0);
+        }
+        by_len[word.len()] += 1;
+        for i in ids.get(mutated).into_iter().flatten() {
+            crack_lens[*i].push(word.len());
+        }
+    }
+    let candidates: u64 = by_len.iter().sum();
+    let mut lens: Vec<usize> = crack_lens.iter().flatten().cloned().collect();
+    lens.sort_unstable();
+    lens.dedup();
+    let required = cracked.len() - (cracked.len() as f64 * max_loss).floor() as usize;
+    let mut best = Gate {
+        min: 0,
+        max: usize::MAX,
+        cracks: cracked.len(),
+        kept: cracked.len(),
+        candidates,
+        saved: 0,
+        by_len: Vec::new(),
+    };
+    for (i, min) in lens.iter().enumerate() {
+        for max in &lens[i..] {
+            // the lengths that can not be written in the rules are not gated
+            let min = if *min <= MAX_POSITION + 1 { *min } else { 0 };
+            let max = if *max < MAX_POSITION {
+                *max
+            } else {
+                usize::MAX
+            };
+            let kept = crack_lens
+                .iter()
+                .filter(|ls| ls.iter().any(|l| *l >= min && *l <= max))
+                .count();
+            if kept < required {
+                continue;
+            }
+            let saved = candidates
+                - by_len
+                    .iter()
+                    .enumerate()
+                    .filter(|(l, _)| *l >= min && *l <= max)
+                    .map(|(_, n)| n)
+                    .sum::<u64>();
+            if saved > best.saved || (saved == best.saved && kept > best.kept) {
+                best = Gate {
+                    min,
+                    max,
+                    kept,
+                    saved,
+                    ..best
+                };
+            }
+        }
+    }
+    best.by_len = by_len;
+    best
+}
+
+impl Gate {
+    // the rejects, that are only needed when they skip candidates
+    pub fn rejects(&self) -> Vec<Rule> {
+        let mut out = Vec::new();
+        let shorter: u64 = self.by_len.iter().take(self.min).sum();
+        let longer: u64 = self.by_len.iter().skip(self.max.saturating_add(1)).sum();
+        if shorter > 0 && self.min <= MAX_POSITION + 1 {
+            out.push(Rule::Reject(RejectRule::UnlessWordLengthMoreThan(
+                Numerical::Val(self.min as u8 - 1),
+            )));
+        }
+        if longer > 0 && self.max < MAX_POSITION {
+            out.push(Rule::Reject(RejectRule::UnlessWordLengthLessThan(
+                Numerical::Val(self.max as u8 + 1),
+            )));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::show_rules;
+    use crate::rules::CommandRule::*;
+    use crate::rules::Rule::Command;
+
+    #[test]
+    fn gates() {
+        let wordlist: Vec<Vec<u8>> = [
+            "ab", "abc", "pass", "hello", "dragon", "letmein", "password",
+        ]
+        .iter()
+        .map(|w| w.as_bytes().to_vec())
+        .collect();
+        let mut lines = HashMap::new();
+        for (i, l) in ["pass1", "hello1", "dragon1", "ab1"].iter().enumerate() {
+            lines.insert(i as u64, l.as_bytes().to_vec());
+        }
+        let rules = vec![Command(Append(b'1'))];
+        let g = gate(&rules, &wordlist, &lines, &[0, 1, 2, 3], 0.0);
+        assert_eq!((g.min, g.max, g.kept, g.saved), (2, 6, 4, 2));
+        assert_eq!(show_rules(&g.rejects(), false).unwrap(), "<7");
+        // the short word can be left out
+        let g = gate(&rules, &wordlist, &lines, &[0, 1, 2, 3], 0.25);
+        assert_eq!((g.min, g.max, g.kept, g.saved), (4, 6, 3, 4));
+        assert_eq!(show_rules(&g.rejects(), false).unwrap(), ">3<7");
+    }
+}
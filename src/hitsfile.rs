@@ -0,0 +1,868 @@
+use crate::rules::{CharClass, CharSelector, CommandRule, Numerical, RejectRule, Rule, UserVar};
+use crate::selection::{HitMap, RuleHits};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// File layout, all integers being LEB128 encoded:
+//  * magic
+//  * wordlist size
+//  * amount of rules, and amount of rules that were actually evaluated
+//  * minimum amount of cracks of the saved rules
+//  * cleartext count, then for each cleartext: its id, length and content
+//  * rule count, then for each rule: the encoded rule chain, the amount of candidates, the amount
+//    of cracked ids and the delta-encoded sorted ids
+static MAGIC: &[u8] = b"RFHITS02";
+// Checkpoints contain the run parameters, so that a resumed run can be checked for consistency,
+// the sorted indices of the evaluated rules, and the hits accumulated so far.
+static CHECKPOINT_MAGIC: &[u8] = b"RFCKPT07";
+
+pub struct SavedHits {
+    pub wordlist_len: u64,
+    pub rules_count: u64,
+    // lower than rules_count when the evaluation was interrupted
+    pub evaluated: u64,
+    // rules cracking less passwords have been dropped
+    pub cutoff: u64,
+    pub cleartexts: HashMap<u64, Vec<u8>>,
+    pub hits: HitMap,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub fn write_varint<W: Write>(w: &mut W, mut v: u64) -> io::Result<()> {
+    let mut buf = [0u8; 10];
+    let mut i = 0;
+    loop {
+        let b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            buf[i] = b;
+            i += 1;
+            break;
+        }
+        buf[i] = b | 0x80;
+        i += 1;
+    }
+    w.write_all(&buf[..i])
+}
+
+pub fn read_varint<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut out: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let mut b = [0u8; 1];
+        r.read_exact(&mut b)?;
+        if shift > 63 {
+            return Err(invalid("varint overflow"));
+        }
+        out |= ((b[0] & 0x7f) as u64) << shift;
+        if b[0] & 0x80 == 0 {
+            return Ok(out);
+        }
+        shift += 7;
+    }
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut b = [0u8; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+
+fn write_bytes<W: Write>(w: &mut W, b: &[u8]) -> io::Result<()> {
+    write_varint(w, b.len() as u64)?;
+    w.write_all(b)
+}
+
+fn read_bytes<R: Read>(r: &mut R) -> io::Result<Vec<u8>> {
+    let ln = read_varint(r)? as usize;
+    let mut o = vec![0; ln];
+    r.read_exact(&mut o)?;
+    Ok(o)
+}
+
+fn encode_uservar(uv: &UserVar, o: &mut Vec<u8>) {
+    use UserVar::*;
+    o.push(match uv {
+        UVA => 0,
+        UVB => 1,
+        UVC => 2,
+        UVD => 3,
+        UVE => 4,
+        UVF => 5,
+        UVG => 6,
+        UVH => 7,
+        UVI => 8,
+        UVJ => 9,
+        UVK => 10,
+    })
+}
+
+fn decode_uservar<R: Read>(r: &mut R) -> io::Result<UserVar> {
+    use UserVar::*;
+    Ok(match read_u8(r)? {
+        0 => UVA,
+        1 => UVB,
+        2 => UVC,
+        3 => UVD,
+        4 => UVE,
+        5 => UVF,
+        6 => UVG,
+        7 => UVH,
+        8 => UVI,
+        9 => UVJ,
+        10 => UVK,
+        _ => return Err(invalid("invalid user variable")),
+    })
+}
+
+fn encode_num(n: &Numerical, o: &mut Vec<u8>) {
+    use Numerical::*;
+    match n {
+        Val(x) => {
+            o.push(0);
+            o.push(*x);
+        }
+        MinLen => o.push(1),
+        MinLenMinus1 => o.push(2),
+        MinLenPlus1 => o.push(3),
+        MaxLen => o.push(4),
+        MaxLenMinus1 => o.push(5),
+        MaxLenPlus1 => o.push(6),
+        SavedLen(uv) => {
+            o.push(7);
+            encode_uservar(uv, o);
+        }
+        WordLen => o.push(8),
+        WordLastCharPos => o.push(9),
+        LastFound => o.push(10),
+        Infinite => o.push(11),
+    }
+}
+
+fn decode_num<R: Read>(r: &mut R) -> io::Result<Numerical> {
+    use Numerical::*;
+    Ok(match read_u8(r)? {
+        0 => Val(read_u8(r)?),
+        1 => MinLen,
+        2 => MinLenMinus1,
+        3 => MinLenPlus1,
+        4 => MaxLen,
+        5 => MaxLenMinus1,
+        6 => MaxLenPlus1,
+        7 => SavedLen(decode_uservar(r)?),
+        8 => WordLen,
+        9 => WordLastCharPos,
+        10 => LastFound,
+        11 => Infinite,
+        _ => return Err(invalid("invalid numerical")),
+    })
+}
+
+fn encode_cs(cs: &CharSelector, o: &mut Vec<u8>) {
+    use CharClass::*;
+    let (neg, cc) = match cs {
+        CharSelector::OneOf(cc) => (0, cc),
+        CharSelector::NoneOf(cc) => (0x80, cc),
+    };
+    let tag = match cc {
+        CCVowels => 0,
+        CCConsonants => 1,
+        CCWhitespace => 2,
+        CCPunctuation => 3,
+        CCSymbols => 4,
+        CCLower => 5,
+        CCUpper => 6,
+        CCDigits => 7,
+        CCLetters => 8,
+        CCAlphaNum => 9,
+        CCControl => 10,
+        CCAll => 11,
+        CCBit8 => 12,
+        CCSingle(_) => 13,
+    };
+    o.push(neg | tag);
+    if let CCSingle(c) = cc {
+        o.push(*c);
+    }
+}
+
+fn decode_cs<R: Read>(r: &mut R) -> io::Result<CharSelector> {
+    use CharClass::*;
+    let tag = read_u8(r)?;
+    let cc = match tag & 0x7f {
+        0 => CCVowels,
+        1 => CCConsonants,
+        2 => CCWhitespace,
+        3 => CCPunctuation,
+        4 => CCSymbols,
+        5 => CCLower,
+        6 => CCUpper,
+        7 => CCDigits,
+        8 => CCLetters,
+        9 => CCAlphaNum,
+        10 => CCControl,
+        11 => CCAll,
+        12 => CCBit8,
+        13 => CCSingle(read_u8(r)?),
+        _ => return Err(invalid("invalid character class")),
+    };
+    Ok(if tag & 0x80 == 0 {
+        CharSelector::OneOf(cc)
+    } else {
+        CharSelector::NoneOf(cc)
+    })
+}
+
+fn encode_reject(rj: &RejectRule, o: &mut Vec<u8>) {
+    use RejectRule::*;
+    match rj {
+        Noop => o.push(0),
+        UnlessCaseSensitive => o.push(1),
+        Unless8bits => o.push(2),
+        UnlessSplit => o.push(3),
+        UnlessWordPairs => o.push(4),
+        UnlessUtf8 => o.push(5),
+        IfUtf8 => o.push(6),
+        UnlessSupportedLengthOrLonger(n) => {
+            o.push(7);
+            encode_num(n, o);
+        }
+        UnlessSupportedLengthOrShorted(n) => {
+            o.push(8);
+            encode_num(n, o);
+        }
+        UnlessWordLengthLessThan(n) => {
+            o.push(9);
+            encode_num(n, o);
+        }
+        UnlessWordLengthMoreThan(n) => {
+            o.push(10);
+            encode_num(n, o);
+        }
+        UnlessWordLengthIs(n) => {
+            o.push(11);
+            encode_num(n, o);
+        }
+        UnlessValidAfterAdding(n) => {
+            o.push(12);
+            encode_num(n, o);
+        }
+        UnlessValidAfterRemoving(n) => {
+            o.push(13);
+            encode_num(n, o);
+        }
+        IfContain(cs) => {
+            o.push(14);
+            encode_cs(cs, o);
+        }
+        UnlessContain(cs) => {
+            o.push(15);
+            encode_cs(cs, o);
+        }
+        UnlessCharAt(n, cs) => {
+            o.push(16);
+            encode_num(n, o);
+            encode_cs(cs, o);
+        }
+        UnlessFirstChar(cs) => {
+            o.push(17);
+            encode_cs(cs, o);
+        }
+        UnlessLastChar(cs) => {
+            o.push(18);
+            encode_cs(cs, o);
+        }
+        UnlessAtLeastNTimes(n, cs) => {
+            o.push(19);
+            encode_num(n, o);
+            encode_cs(cs, o);
+        }
+        UnlessValidUtf8 => o.push(20),
+        RejectTheWordUnlessDifferent => o.push(21),
+    }
+}
+
+fn decode_reject<R: Read>(r: &mut R) -> io::Result<RejectRule> {
+    use RejectRule::*;
+    Ok(match read_u8(r)? {
+        0 => Noop,
+        1 => UnlessCaseSensitive,
+        2 => Unless8bits,
+        3 => UnlessSplit,
+        4 => UnlessWordPairs,
+        5 => UnlessUtf8,
+        6 => IfUtf8,
+        7 => UnlessSupportedLengthOrLonger(decode_num(r)?),
+        8 => UnlessSupportedLengthOrShorted(decode_num(r)?),
+        9 => UnlessWordLengthLessThan(decode_num(r)?),
+        10 => UnlessWordLengthMoreThan(decode_num(r)?),
+        11 => UnlessWordLengthIs(decode_num(r)?),
+        12 => UnlessValidAfterAdding(decode_num(r)?),
+        13 => UnlessValidAfterRemoving(decode_num(r)?),
+        14 => IfContain(decode_cs(r)?),
+        15 => UnlessContain(decode_cs(r)?),
+        16 => UnlessCharAt(decode_num(r)?, decode_cs(r)?),
+        17 => UnlessFirstChar(decode_cs(r)?),
+        18 => UnlessLastChar(decode_cs(r)?),
+        19 => UnlessAtLeastNTimes(decode_num(r)?, decode_cs(r)?),
+        20 => UnlessValidUtf8,
+        21 => RejectTheWordUnlessDifferent,
+        _ => return Err(invalid("invalid reject rule")),
+    })
+}
+
+fn encode_command(cmd: &CommandRule, o: &mut Vec<u8>) {
+    use CommandRule::*;
+    let simple = |o: &mut Vec<u8>, tag: u8| o.push(tag);
+    let num1 = |o: &mut Vec<u8>, tag: u8, n: &Numerical| {
+        o.push(tag);
+        encode_num(n, o);
+    };
+    let num2 = |o: &mut Vec<u8>, tag: u8, n: &Numerical, m: &Numerical| {
+        o.push(tag);
+        encode_num(n, o);
+        encode_num(m, o);
+    };
+    let numc = |o: &mut Vec<u8>, tag: u8, n: &Numerical, c: u8| {
+        o.push(tag);
+        encode_num(n, o);
+        o.push(c);
+    };
+    match cmd {
+        Noop => simple(o, 0),
+        ToLower => simple(o, 1),
+        ToUpper => simple(o, 2),
+        Capitalize => simple(o, 3),
+        InvertCapitalize => simple(o, 4),
+        ToggleAll => simple(o, 5),
+        ShiftAll => simple(o, 6),
+        LowerVowelsUpperConsonants => simple(o, 7),
+        ShiftAllKeyboardRight => simple(o, 8),
+        ShiftAllKeyboardLeft => simple(o, 9),
+        Reverse => simple(o, 10),
+        Duplicate => simple(o, 11),
+        Reflect => simple(o, 12),
+        RotLeft => simple(o, 13),
+        RotRight => simple(o, 14),
+        SwapFirstTwo => simple(o, 15),
+        SwapLastTwo => simple(o, 16),
+        AppendMemory => simple(o, 17),
+        PrependMemory => simple(o, 18),
+        DupeAllChar => simple(o, 19),
+        Pluralize => simple(o, 20),
+        PastTense => simple(o, 21),
+        Genitive => simple(o, 22),
+        DeleteFirst => simple(o, 23),
+        DeleteLast => simple(o, 24),
+        Memorize => simple(o, 25),
+        ToggleCase(n) => num1(o, 26, n),
+        ToggleShift(n) => num1(o, 27, n),
+        DeleteAt(n) => num1(o, 28, n),
+        PurgeAll(cs) => {
+            o.push(29);
+            encode_cs(cs, o);
+        }
+        TitleCase(cs) => {
+            o.push(30);
+            encode_cs(cs, o);
+        }
+        DupWordNTimes(n) => num1(o, 31, n),
+        BitshiftRight(n) => num1(o, 32, n),
+        BitshiftLeft(n) => num1(o, 33, n),
+        Swap(n, m) => num2(o, 34, n, m),
+        Increment(n) => num1(o, 35, n),
+        Decrement(n) => num1(o, 36, n),
+        DupeFirstChar(n) => num1(o, 37, n),
+        DupeLastChar(n) => num1(o, 38, n),
+        ReplaceWithNext(n) => num1(o, 39, n),
+        ReplaceWithPrior(n) => num1(o, 40, n),
+        DupFirstString(n) => num1(o, 41, n),
+        DupLastString(n) => num1(o, 42, n),
+        Append(c) => {
+            o.push(43);
+            o.push(*c);
+        }
+        Prefix(c) => {
+            o.push(44);
+            o.push(*c);
+        }
+        OmitRange(n, m) => num2(o, 45, n, m),
+        InsertChar(n, c) => numc(o, 46, n, *c),
+        Overstrike(n, c) => numc(o, 47, n, *c),
+        Extract(n, m) => num2(o, 48, n, m),
+        ReplaceAll(cs, c) => {
+            o.push(49);
+            encode_cs(cs, o);
+            o.push(*c);
+        }
+        Truncate(n) => num1(o, 50, n),
+        InsertString(n, s) => {
+            num1(o, 51, n);
+            // writing to a vector can't fail
+            write_bytes(o, s).unwrap();
+        }
+        ExtractInsert(n, m, p) => {
+            num2(o, 52, n, m);
+            encode_num(p, o);
+        }
+        MemoryAssign(uv, n, m) => {
+            o.push(53);
+            encode_uservar(uv, o);
+            encode_num(n, o);
+            encode_num(m, o);
+        }
+    }
+}
+
+fn decode_command<R: Read>(r: &mut R) -> io::Result<CommandRule> {
+    use CommandRule::*;
+    Ok(match read_u8(r)? {
+        0 => Noop,
+        1 => ToLower,
+        2 => ToUpper,
+        3 => Capitalize,
+        4 => InvertCapitalize,
+        5 => ToggleAll,
+        6 => ShiftAll,
+        7 => LowerVowelsUpperConsonants,
+        8 => ShiftAllKeyboardRight,
+        9 => ShiftAllKeyboardLeft,
+        10 => Reverse,
+        11 => Duplicate,
+        12 => Reflect,
+        13 => RotLeft,
+        14 => RotRight,
+        15 => SwapFirstTwo,
+        16 => SwapLastTwo,
+        17 => AppendMemory,
+        18 => PrependMemory,
+        19 => DupeAllChar,
+        20 => Pluralize,
+        21 => PastTense,
+        22 => Genitive,
+        23 => DeleteFirst,
+        24 => DeleteLast,
+        25 => Memorize,
+        26 => ToggleCase(decode_num(r)?),
+        27 => ToggleShift(decode_num(r)?),
+        28 => DeleteAt(decode_num(r)?),
+        29 => PurgeAll(decode_cs(r)?),
+        30 => TitleCase(decode_cs(r)?),
+        31 => DupWordNTimes(decode_num(r)?),
+        32 => BitshiftRight(decode_num(r)?),
+        33 => BitshiftLeft(decode_num(r)?),
+        34 => Swap(decode_num(r)?, decode_num(r)?),
+        35 => Increment(decode_num(r)?),
+        36 => Decrement(decode_num(r)?),
+        37 => DupeFirstChar(decode_num(r)?),
+        38 => DupeLastChar(decode_num(r)?),
+        39 => ReplaceWithNext(decode_num(r)?),
+        40 => ReplaceWithPrior(decode_num(r)?),
+        41 => DupFirstString(decode_num(r)?),
+        42 => DupLastString(decode_num(r)?),
+        43 => Append(read_u8(r)?),
+        44 => Prefix(read_u8(r)?),
+        45 => OmitRange(decode_num(r)?, decode_num(r)?),
+        46 => InsertChar(decode_num(r)?, read_u8(r)?),
+        47 => Overstrike(decode_num(r)?, read_u8(r)?),
+        48 => Extract(decode_num(r)?, decode_num(r)?),
+        49 => ReplaceAll(decode_cs(r)?, read_u8(r)?),
+        50 => Truncate(decode_num(r)?),
+        51 => InsertString(decode_num(r)?, read_bytes(r)?),
+        52 => ExtractInsert(decode_num(r)?, decode_num(r)?, decode_num(r)?),
+        53 => MemoryAssign(decode_uservar(r)?, decode_num(r)?, decode_num(r)?),
+        _ => return Err(invalid("invalid command")),
+    })
+}
+
+pub fn encode_rules(rules: &[Rule], o: &mut Vec<u8>) {
+    write_varint(o, rules.len() as u64).unwrap();
+    for r in rules {
+        match r {
+            Rule::Reject(rj) => {
+                o.push(0);
+                encode_reject(rj, o);
+            }
+            Rule::Command(cmd) => {
+                o.push(1);
+                encode_command(cmd, o);
+            }
+        }
+    }
+}
+
+pub fn decode_rules<R: Read>(r: &mut R) -> io::Result<Vec<Rule>> {
+    let ln = read_varint(r)?;
+    let mut o = Vec::new();
+    for _ in 0..ln {
+        o.push(match read_u8(r)? {
+            0 => Rule::Reject(decode_reject(r)?),
+            1 => Rule::Command(decode_command(r)?),
+            _ => return Err(invalid("invalid rule")),
+        });
+    }
+    Ok(o)
+}
+
+pub fn write_rule_hits<W: Write>(w: &mut W, rules: &[Rule], rh: &RuleHits) -> io::Result<()> {
+    let mut enc = Vec::new();
+    encode_rules(rules, &mut enc);
+    w.write_all(&enc)?;
+    write_varint(w, rh.candidates)?;
+    write_varint(w, rh.cracked.len() as u64)?;
+    let mut prev = 0;
+    for id in &rh.cracked {
+        write_varint(w, id - prev)?;
+        prev = *id;
+    }
+    Ok(())
+}
+
+pub fn read_rule_hits<R: Read>(r: &mut R) -> io::Result<(Vec<Rule>, RuleHits)> {
+    let rules = decode_rules(r)?;
+    let candidates = read_varint(r)?;
+    let ln = read_varint(r)? as usize;
+    let mut cracked = Vec::with_capacity(ln);
+    let mut prev = 0;
+    for _ in 0..ln {
+        prev += read_varint(r)?;
+        cracked.push(prev);
+    }
+    Ok((
+        rules,
+        RuleHits {
+            candidates,
+            cracked,
+        },
+    ))
+}
+
+pub fn save(path: &Path, saved: &SavedHits) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    w.write_all(MAGIC)?;
+    write_varint(&mut w, saved.wordlist_len)?;
+    write_varint(&mut w, saved.rules_count)?;
+    write_varint(&mut w, saved.evaluated)?;
+    write_varint(&mut w, saved.cutoff)?;
+    write_varint(&mut w, saved.cleartexts.len() as u64)?;
+    let mut ids: Vec<&u64> = saved.cleartexts.keys().collect();
+    ids.sort();
+    for id in ids {
+        write_varint(&mut w, *id)?;
+        write_bytes(&mut w, &saved.cleartexts[id])?;
+    }
+    write_varint(&mut w, saved.hits.len() as u64)?;
+    for (rules, rh) in &saved.hits {
+        write_rule_hits(&mut w, rules, rh)?;
+    }
+    w.flush()
+}
+
+pub fn load(path: &Path) -> io::Result<SavedHits> {
+    let mut r = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(invalid("not a rulesfinder hits file"));
+    }
+    let wordlist_len = read_varint(&mut r)?;
+    let rules_count = read_varint(&mut r)?;
+    let evaluated = read_varint(&mut r)?;
+    let cutoff = read_varint(&mut r)?;
+    let ncleartexts = read_varint(&mut r)?;
+    let mut cleartexts = HashMap::new();
+    for _ in 0..ncleartexts {
+        let id = read_varint(&mut r)?;
+        cleartexts.insert(id, read_bytes(&mut r)?);
+    }
+    let nhits = read_varint(&mut r)?;
+    let mut hits = HashMap::new();
+    for _ in 0..nhits {
+        let (rules, rh) = read_rule_hits(&mut r)?;
+        hits.insert(rules, rh);
+    }
+    Ok(SavedHits {
+        wordlist_len,
+        rules_count,
+        evaluated,
+        cutoff,
+        cleartexts,
+        hits,
+    })
+}
+
+// parameters that must not change between a checkpoint and a resumed run
+#[derive(Debug, PartialEq)]
+pub struct RunParameters {
+    pub rules_count: u64,
+    pub cutoff: u64,
+    pub minsize: u64,
+    pub hashcat: bool,
+    pub cleartexts_count: u64,
+    pub max_insertion: u64,
+    pub edits: bool,
+    pub substitutions: u64,
+    pub casefold: bool,
+    // fingerprints of the ordered rule list, and of the wordlist and cleartexts
+    pub rules_hash: u64,
+    pub inputs_hash: u64,
+}
+
+// FNV-1a, each slice being prefixed with its length so that their boundaries matter
+pub struct Fingerprint(u64);
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        Fingerprint(0xcbf29ce484222325)
+    }
+
+    pub fn add(&mut self, data: &[u8]) {
+        let mut len = Vec::new();
+        write_varint(&mut len, data.len() as u64).unwrap();
+        for b in len.iter().chain(data) {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub fn rules_fingerprint(rules: &[Vec<Rule>]) -> u64 {
+    let mut fp = Fingerprint::new();
+    let mut enc = Vec::new();
+    for rs in rules {
+        enc.clear();
+        encode_rules(rs, &mut enc);
+        fp.add(&enc);
+    }
+    fp.finish()
+}
+
+// the cleartexts are hashed in id order
+pub fn inputs_fingerprint(wordlist: &[Vec<u8>], cleartexts: &HashMap<u64, Vec<u8>>) -> u64 {
+    let mut fp = Fingerprint::new();
+    for w in wordlist {
+        fp.add(w);
+    }
+    let mut ids: Vec<&u64> = cleartexts.keys().collect();
+    ids.sort();
+    for id in ids {
+        fp.add(&id.to_le_bytes());
+        fp.add(&cleartexts[id]);
+    }
+    fp.finish()
+}
+
+pub struct Checkpoint {
+    pub parameters: RunParameters,
+    // raised when hits are pruned
+    pub threshold: u64,
+    pub done: Vec<u64>,
+    pub hits: HitMap,
+}
+
+// the checkpoint is written to a temporary file first, so that a crash while writing does not
+// destroy the previous one
+pub fn save_checkpoint(
+    path: &Path,
+    parameters: &RunParameters,
+    threshold: u64,
+    done: &[bool],
+    hits: &HitMap,
+) -> io::Result<()> {
+    let mut tmppath = path.as_os_str().to_owned();
+    tmppath.push(".tmp");
+    {
+        let mut w = BufWriter::new(File::create(&tmppath)?);
+        w.write_all(CHECKPOINT_MAGIC)?;
+        write_varint(&mut w, parameters.rules_count)?;
+        write_varint(&mut w, parameters.cutoff)?;
+        write_varint(&mut w, parameters.minsize)?;
+        write_varint(&mut w, parameters.hashcat as u64)?;
+        write_varint(&mut w, parameters.cleartexts_count)?;
+        write_varint(&mut w, parameters.max_insertion)?;
+        write_varint(&mut w, parameters.edits as u64)?;
+        write_varint(&mut w, parameters.substitutions)?;
+        write_varint(&mut w, parameters.casefold as u64)?;
+        w.write_all(&parameters.rules_hash.to_le_bytes())?;
+        w.write_all(&parameters.inputs_hash.to_le_bytes())?;
+        write_varint(&mut w, threshold)?;
+        write_varint(&mut w, done.iter().filter(|d| **d).count() as u64)?;
+        let mut prev = 0;
+        for (i, _) in done.iter().enumerate().filter(|(_, d)| **d) {
+            write_varint(&mut w, i as u64 - prev)?;
+            prev = i as u64;
+        }
+        write_varint(&mut w, hits.len() as u64)?;
+        for (rules, rh) in hits {
+            write_rule_hits(&mut w, rules, rh)?;
+        }
+        w.flush()?;
+    }
+    std::fs::rename(&tmppath, path)
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub fn load_checkpoint(path: &Path) -> io::Result<Checkpoint> {
+    let mut r = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 8];
+    r.read_exact(&mut magic)?;
+    if magic != CHECKPOINT_MAGIC {
+        return Err(invalid("not a rulesfinder checkpoint file"));
+    }
+    let parameters = RunParameters {
+        rules_count: read_varint(&mut r)?,
+        cutoff: read_varint(&mut r)?,
+        minsize: read_varint(&mut r)?,
+        hashcat: read_varint(&mut r)? != 0,
+        cleartexts_count: read_varint(&mut r)?,
+        max_insertion: read_varint(&mut r)?,
+        edits: read_varint(&mut r)? != 0,
+        substitutions: read_varint(&mut r)?,
+        casefold: read_varint(&mut r)? != 0,
+        rules_hash: read_u64(&mut r)?,
+        inputs_hash: read_u64(&mut r)?,
+    };
+    let threshold = read_varint(&mut r)?;
+    let ndone = read_varint(&mut r)?;
+    let mut done = Vec::with_capacity(ndone as usize);
+    let mut prev = 0;
+    for _ in 0..ndone {
+        prev += read_varint(&mut r)?;
+        done.push(prev);
+    }
+    let nhits = read_varint(&mut r)?;
+    let mut hits = HashMap::new();
+    for _ in 0..nhits {
+        let (rules, rh) = read_rule_hits(&mut r)?;
+        hits.insert(rules, rh);
+    }
+    Ok(Checkpoint {
+        parameters,
+        threshold,
+        done,
+        hits,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules;
+
+    #[test]
+    fn varint() {
+        for &v in &[0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut o = Vec::new();
+            write_varint(&mut o, v).unwrap();
+            assert_eq!(read_varint(&mut o.as_slice()).unwrap(), v);
+        }
+    }
+
+    #[test]
+    fn generated_rules() {
+        for rs in rules::genmutate() {
+            let mut o = Vec::new();
+            encode_rules(&rs, &mut o);
+            assert_eq!(decode_rules(&mut o.as_slice()).unwrap(), rs);
+        }
+    }
+
+    #[test]
+    fn rule_hits() {
+        use rules::CommandRule::*;
+        use rules::Numerical::*;
+        use rules::RejectRule::*;
+        let rs = vec![
+            Rule::Reject(UnlessCharAt(
+                Val(3),
+                CharSelector::NoneOf(CharClass::CCDigits),
+            )),
+            Rule::Command(MemoryAssign(UserVar::UVC, WordLen, Val(2))),
+            Rule::Command(InsertString(Infinite, b"2023".to_vec())),
+        ];
+        let rh = RuleHits {
+            candidates: 123456,
+            cracked: vec![1, 5, 1000, 1 << 40],
+        };
+        let mut o = Vec::new();
+        write_rule_hits(&mut o, &rs, &rh).unwrap();
+        let (drs, drh) = read_rule_hits(&mut o.as_slice()).unwrap();
+        assert_eq!(drs, rs);
+        assert_eq!(drh.candidates, rh.candidates);
+        assert_eq!(drh.cracked, rh.cracked);
+
+        // long chains are not truncated
+        let long = vec![Rule::Command(Append(b'1')); 300];
+        let mut o = Vec::new();
+        encode_rules(&long, &mut o);
+        assert_eq!(decode_rules(&mut o.as_slice()).unwrap(), long);
+    }
+
+    #[test]
+    fn checkpoint() {
+        use rules::CommandRule::*;
+        let parameters = RunParameters {
+            rules_count: 5,
+            cutoff: 50,
+            minsize: 4,
+            hashcat: true,
+            cleartexts_count: 1000,
+            max_insertion: 2,
+            edits: true,
+            substitutions: 8,
+            casefold: true,
+            rules_hash: rules_fingerprint(&[vec![Rule::Command(Append(b'1'))]]),
+            inputs_hash: inputs_fingerprint(&[b"word".to_vec()], &HashMap::new()),
+        };
+        let mut hits = HashMap::new();
+        hits.insert(
+            vec![Rule::Command(Append(b'1'))],
+            RuleHits {
+                candidates: 10,
+                cracked: vec![3, 4],
+            },
+        );
+        let path = std::env::temp_dir().join(format!("rf-checkpoint-{}", std::process::id()));
+        save_checkpoint(
+            &path,
+            &parameters,
+            60,
+            &[true, false, false, true, true],
+            &hits,
+        )
+        .unwrap();
+        let ck = load_checkpoint(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(ck.parameters, parameters);
+        // the boundaries between the inputs matter
+        assert_ne!(
+            inputs_fingerprint(&[b"ab".to_vec(), b"c".to_vec()], &HashMap::new()),
+            inputs_fingerprint(&[b"a".to_vec(), b"bc".to_vec()], &HashMap::new())
+        );
+        assert_ne!(
+            parameters.rules_hash,
+            rules_fingerprint(&[vec![Rule::Command(Append(b'2'))]])
+        );
+        assert_eq!(ck.threshold, 60);
+        assert_eq!(ck.done, vec![0, 3, 4]);
+        assert_eq!(ck.hits.len(), 1);
+        assert_eq!(
+            ck.hits[&vec![Rule::Command(Append(b'1'))]].cracked,
+            vec![3, 4]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use crate::compiled;
+use crate::rules::Rule;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+// Cleartexts that are kept out of the fragment index, and only used to check how the selected
+// rules perform on passwords they were not trained on.
+
+// moves a fraction of the cleartexts to the test set, the split only depending on the contents
+// of the lines
+pub fn split(lines: &mut HashMap<u64, Vec<u8>>, fraction: f64) -> Vec<Vec<u8>> {
+    let threshold = (fraction * u32::MAX as f64) as u64;
+    let is_test = |line: &[u8]| {
+        let mut hasher = DefaultHasher::new();
+        line.hash(&mut hasher);
+        hasher.finish() & u32::MAX as u64 <= threshold
+    };
+    let mut ids: Vec<u64> = lines
+        .iter()
+        .filter(|(_, l)| is_test(l))
+        .map(|(i, _)| *i)
+        .collect();
+    ids.sort_unstable();
+    ids.iter().map(|i| lines.remove(i).unwrap()).collect()
+}
+
+pub struct TestSet {
+    wordlist: Arc<Vec<Vec<u8>>>,
+    // cleartext -> amount of lines
+    cleartexts: HashMap<Vec<u8>, usize>,
+    pub total: usize,
+    pub cracked: usize,
+}
+
+impl TestSet {
+    pub fn new(wordlist: Arc<Vec<Vec<u8>>>, lines: Vec<Vec<u8>>) -> Self {
+        let total = lines.len();
+        let mut cleartexts = HashMap::new();
+        for l in lines {
+            *cleartexts.entry(l).or_default() += 1;
+        }
+        TestSet {
+            wordlist,
+            cleartexts,
+            total,
+            cracked: 0,
+        }
+    }
+
+    // amount of test cleartexts that were not already cracked, and are cracked by the rules
+    pub fn evaluate(&mut self, rules: &[Rule]) -> usize {
+        let program = compiled::compile(rules);
+        let mut executor = compiled::Executor::new();
+        let mut found = 0;
+        for word in self.wordlist.iter() {
+            if let Some(mutated) = executor.run(&program, word) {
+                if let Some(n) = self.cleartexts.remove(mutated) {
+                    found += n;
+                }
+            }
+        }
+        self.cracked += found;
+        found
+    }
+
+    pub fn percent(&self) -> f64 {
+        self.cracked as f64 * 100.0 / self.total.max(1) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::CommandRule::*;
+    use crate::rules::Rule::Command;
+
+    #[test]
+    fn holdout() {
+        let mut lines = HashMap::new();
+        for i in 0..1000u64 {
+            lines.insert(i, format!("password{}", i).into_bytes());
+        }
+        let test = split(&mut lines, 0.2);
+        assert!(test.len() > 150 && test.len() < 250);
+        assert_eq!(lines.len() + test.len(), 1000);
+        assert!(test.iter().all(|l| !lines.values().any(|t| t == l)));
+
+        let wordlist = Arc::new(vec![b"password".to_vec(), b"hello".to_vec()]);
+        let lines = ["password1", "password1", "Password", "hello2", "other"];
+        let mut testset = TestSet::new(
+            wordlist,
+            lines.iter().map(|l| l.as_bytes().to_vec()).collect(),
+        );
+        assert_eq!(testset.evaluate(&[Command(Append(b'1'))]), 2);
+        assert_eq!(testset.evaluate(&[Command(Append(b'1'))]), 0);
+        assert_eq!(testset.evaluate(&[Command(Capitalize)]), 1);
+        assert_eq!(testset.cracked, 3);
+        assert_eq!(testset.percent(), 60.0);
+    }
+}
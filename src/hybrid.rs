@@ -0,0 +1,247 @@
+use crate::rules::{self, CommandRule, Numerical, Rule};
+use crate::selection::{shorter_rules, HitMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+// Many learned rules are a base transformation followed by a prefix or suffix of digits or
+// symbols. Grouping them by base rule and character class shape gives hashcat hybrid attacks
+// (-a 6 and -a 7), where a single mask replaces thousands of $ rules.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Side {
+    // -a 6, the mask is appended
+    Append,
+    // -a 7, the mask is prepended
+    Prepend,
+}
+
+pub struct Mask {
+    pub mask: String,
+    pub keyspace: u64,
+    pub cracked: BTreeSet<u64>,
+}
+
+pub struct Job {
+    pub side: Side,
+    pub base: Vec<Rule>,
+    // amount of candidates of the base rule
+    pub words: u64,
+    // best masks first
+    pub masks: Vec<Mask>,
+    pub cracked: BTreeSet<u64>,
+}
+
+// splits the rule between the base rule and its learned prefix or suffix
+fn split_affix(rules: &[Rule]) -> Option<(Vec<Rule>, Side, Vec<u8>)> {
+    use CommandRule::*;
+    let (last, base) = rules.split_last()?;
+    let (side, affix) = match last {
+        Rule::Command(Append(c)) => (Side::Append, vec![*c]),
+        Rule::Command(InsertString(Numerical::Infinite, s)) => (Side::Append, s.clone()),
+        Rule::Command(Prefix(c)) => (Side::Prepend, vec![*c]),
+        Rule::Command(InsertString(Numerical::Val(0), s)) => (Side::Prepend, s.clone()),
+        _ => return None,
+    };
+    // rules with both a prefix and a suffix are not hybrid attacks
+    if let Some(Rule::Command(Prefix(_)))
+    | Some(Rule::Command(InsertString(Numerical::Val(0), _))) = base.last()
+    {
+        return None;
+    }
+    Some((base.to_vec(), side, affix))
+}
+
+// the hashcat mask matching the string, and its keyspace
+fn mask_shape(s: &[u8]) -> Option<(String, u64)> {
+    let mut mask = String::new();
+    let mut keyspace: u64 = 1;
+    for c in s {
+        let (m, k) = match c {
+            b'a'..=b'z' => ("?l", 26),
+            b'A'..=b'Z' => ("?u", 26),
+            b'0'..=b'9' => ("?d", 10),
+            b' '..=b'~' => ("?s", 33),
+            _ => return None,
+        };
+        mask += m;
+        keyspace = keyspace.checked_mul(k)?;
+    }
+    Some((mask, keyspace))
+}
+
+// amount of candidates of the base rule, and masks
+type Group = (u64, BTreeMap<String, Mask>);
+
+// hybrid jobs, greedily selected by amount of new cracks, only keeping the masks cracking at least
+// cutoff passwords
+pub fn jobs(hits: &HitMap, cutoff: usize) -> Vec<Job> {
+    let mut groups: HashMap<(Side, Vec<Rule>), Group> = HashMap::new();
+    for (rules, rh) in hits {
+        let (base, side, affix) = match split_affix(rules) {
+            Some(x) => x,
+            None => continue,
+        };
+        // the job is run with hashcat
+        if rules::show_rules(&base, true).is_none() {
+            continue;
+        }
+        let (shape, keyspace) = match mask_shape(&affix) {
+            Some(x) => x,
+            None => continue,
+        };
+        let group = groups.entry((side, base)).or_default();
+        group.0 = group.0.max(rh.candidates);
+        group
+            .1
+            .entry(shape.clone())
+            .or_insert_with(|| Mask {
+                mask: shape,
+                keyspace,
+                cracked: BTreeSet::new(),
+            })
+            .cracked
+            .extend(rh.cracked.iter().cloned());
+    }
+    let mut candidates: Vec<Job> = groups
+        .into_iter()
+        .filter_map(|((side, base), (words, masks))| {
+            let mut masks: Vec<Mask> = masks
+                .into_values()
+                .filter(|m| m.cracked.len() >= cutoff.max(1))
+                .collect();
+            if masks.is_empty() {
+                return None;
+            }
+            // the most cracks per candidate first
+            masks.sort_by(|a, b| {
+                let ea = a.cracked.len() as f64 / a.keyspace as f64;
+                let eb = b.cracked.len() as f64 / b.keyspace as f64;
+                eb.total_cmp(&ea).then_with(|| a.mask.cmp(&b.mask))
+            });
+            let cracked = masks
+                .iter()
+                .flat_map(|m| m.cracked.iter().cloned())
+                .collect();
+            Some(Job {
+                side,
+                base,
+                words,
+                masks,
+                cracked,
+            })
+        })
+        .collect();
+    // many base rules behave the same on most words, so the jobs overlap
+    let mut out = Vec::new();
+    let mut covered = BTreeSet::new();
+    loop {
+        let mut best: Option<(usize, usize)> = None;
+        for (i, job) in candidates.iter().enumerate() {
+            let new = job.cracked.difference(&covered).count();
+            let better = match best {
+                None => true,
+                Some((b, bnew)) => {
+                    new > bnew
+                        || (new == bnew
+                            && (job.side, &job.base) != (candidates[b].side, &candidates[b].base)
+                            && (job.side < candidates[b].side
+                                || (job.side == candidates[b].side
+                                    && shorter_rules(&job.base, &candidates[b].base))))
+                }
+            };
+            if better {
+                best = Some((i, new));
+            }
+        }
+        match best {
+            Some((i, new)) if new >= cutoff.max(1) => {
+                let job = candidates.swap_remove(i);
+                covered.extend(job.cracked.iter().cloned());
+                out.push(job);
+            }
+            _ => break,
+        }
+    }
+    out
+}
+
+impl Job {
+    pub fn keyspace(&self) -> u64 {
+        self.masks
+            .iter()
+            .map(|m| m.keyspace.saturating_mul(self.words))
+            .fold(0, u64::saturating_add)
+    }
+
+    // the hashcat command line, the wordlist and hashes being placeholders
+    pub fn command(&self, maskfile: &str) -> String {
+        let (mode, ruleflag) = match self.side {
+            Side::Append => (6, "-j"),
+            Side::Prepend => (7, "-k"),
+        };
+        let rule = match self.base.as_slice() {
+            [] | [Rule::Command(CommandRule::Noop)] => String::new(),
+            base => {
+                let shown = rules::show_rules(base, true).unwrap();
+                format!(" {} '{}'", ruleflag, shown.replace('\'', "'\\''"))
+            }
+        };
+        match self.side {
+            Side::Append => format!("hashcat -a {}{} HASHES WORDLIST {}", mode, rule, maskfile),
+            Side::Prepend => format!("hashcat -a {}{} HASHES {} WORDLIST", mode, rule, maskfile),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::CommandRule::*;
+    use crate::rules::Rule::Command;
+    use crate::selection::RuleHits;
+
+    #[test]
+    fn hybrid() {
+        let mut hits = HashMap::new();
+        let mut add = |rules: Vec<Rule>, cracked: &[u64]| {
+            hits.insert(
+                rules,
+                RuleHits {
+                    candidates: 1000,
+                    cracked: cracked.to_vec(),
+                },
+            );
+        };
+        let suffix = |base: CommandRule, s: &str| {
+            vec![
+                Command(base),
+                Command(InsertString(Numerical::Infinite, s.as_bytes().to_vec())),
+            ]
+        };
+        add(suffix(Capitalize, "2019"), &[1, 2]);
+        add(suffix(Capitalize, "1234"), &[2, 3, 4]);
+        add(vec![Command(Capitalize), Command(Append(b'!'))], &[5, 6]);
+        add(vec![Command(Capitalize), Command(Append(b'1'))], &[7]);
+        add(vec![Command(Noop), Command(Prefix(b'1'))], &[8, 9]);
+        add(
+            vec![Command(Prefix(b'1')), Command(Append(b'1'))],
+            &[10, 11],
+        );
+        add(vec![Command(ToLower)], &[12, 13]);
+        let jobs = jobs(&hits, 2);
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].side, Side::Append);
+        assert_eq!(jobs[0].cracked.len(), 6);
+        let masks: Vec<&str> = jobs[0].masks.iter().map(|m| m.mask.as_str()).collect();
+        assert_eq!(masks, vec!["?s", "?d?d?d?d"]);
+        assert_eq!(jobs[0].keyspace(), 1000 * 33 + 1000 * 10000);
+        assert_eq!(
+            jobs[0].command("out-1.hcmask"),
+            "hashcat -a 6 -j 'c' HASHES WORDLIST out-1.hcmask"
+        );
+        assert_eq!(jobs[1].side, Side::Prepend);
+        assert_eq!(
+            jobs[1].command("out-2.hcmask"),
+            "hashcat -a 7 HASHES out-2.hcmask WORDLIST"
+        );
+    }
+}
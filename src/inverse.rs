@@ -0,0 +1,335 @@
+use crate::cleartexts::CleartextMap;
+use crate::compiled;
+use crate::matcher::{finish_hits, record_matches, WorkerHits};
+use crate::rules::{self, CharClass, CharSelector, CommandRule, Numerical, Rule};
+use std::collections::{BTreeSet, HashMap};
+
+// fragments with more possible preimages are checked by mutating the wordlist instead
+const MAX_PREIMAGES: usize = 4096;
+
+// Instead of mutating every word of the wordlist, invertible rules are applied backwards on the
+// cleartext fragments, and the resulting words are looked up in the wordlist. This is faster when
+// the wordlist is larger than the fragment index.
+
+// wordlist index, used to look up words
+pub struct WordIndex {
+    // lowercased word -> positions in the wordlist
+    folded: HashMap<Vec<u8>, Vec<u32>>,
+    // amount of words of each length, longer words being counted in the last entry
+    lengths: Vec<u64>,
+}
+
+impl WordIndex {
+    pub fn new(wordlist: &[Vec<u8>]) -> Self {
+        let mut folded: HashMap<Vec<u8>, Vec<u32>> = HashMap::new();
+        let mut lengths = vec![0; 256];
+        for (i, w) in wordlist.iter().enumerate() {
+            folded
+                .entry(w.to_ascii_lowercase())
+                .or_default()
+                .push(i as u32);
+            lengths[w.len().min(255)] += 1;
+        }
+        WordIndex { folded, lengths }
+    }
+
+    fn contains(&self, wordlist: &[Vec<u8>], word: &[u8]) -> bool {
+        match self.folded.get(&word.to_ascii_lowercase()) {
+            None => false,
+            Some(ids) => ids.iter().any(|&i| wordlist[i as usize] == word),
+        }
+    }
+
+    // amount of words at least min_len long
+    fn count_min_len(&self, min_len: usize) -> u64 {
+        self.lengths[min_len.min(255)..].iter().sum()
+    }
+}
+
+enum Preimage {
+    // a single possible word, computed by applying the inverse rules to the fragment
+    Apply(Vec<Rule>),
+    // rules that repeat the word (d, f)
+    FirstHalf,
+    // q
+    EvenChars,
+    // character substitutions, with the possible source characters of each character
+    CharMap(Vec<Vec<u8>>),
+    // case changes, where all words with the same lowercase form are possible
+    CaseFold,
+}
+
+pub struct Inverse {
+    preimage: Preimage,
+    // the rule returns a candidate for all words at least that long
+    min_len: usize,
+}
+
+fn invert_command(cmd: &CommandRule) -> Option<(CommandRule, usize)> {
+    use CommandRule::*;
+    use Numerical::Val;
+    match cmd {
+        Noop => Some((Noop, 1)),
+        Reverse => Some((Reverse, 1)),
+        ToggleAll => Some((ToggleAll, 1)),
+        RotLeft => Some((RotRight, 1)),
+        RotRight => Some((RotLeft, 1)),
+        SwapFirstTwo => Some((SwapFirstTwo, 2)),
+        SwapLastTwo => Some((SwapLastTwo, 2)),
+        ToggleCase(Val(n)) => Some((ToggleCase(Val(*n)), 1)),
+        Swap(Val(a), Val(b)) => Some((Swap(Val(*a), Val(*b)), 1 + *a.max(b) as usize)),
+        _ => None,
+    }
+}
+
+fn is_substitution(cmd: &CommandRule) -> bool {
+    matches!(
+        cmd,
+        CommandRule::ReplaceAll(CharSelector::OneOf(CharClass::CCSingle(_)), _)
+    )
+}
+
+// returns how to compute the preimages of a fragment, when the rule can be inverted
+pub fn invert(rules: &[Rule]) -> Option<Inverse> {
+    use CommandRule::*;
+    let cmds = rules
+        .iter()
+        .map(|r| match r {
+            Rule::Command(c) => Some(c),
+            Rule::Reject(_) => None,
+        })
+        .collect::<Option<Vec<&CommandRule>>>()?;
+    let preimage = match cmds.as_slice() {
+        [] => return None,
+        [ToUpper] | [ToLower] | [Capitalize] | [InvertCapitalize] => Preimage::CaseFold,
+        [Duplicate] | [Reflect] => Preimage::FirstHalf,
+        [DupeAllChar] => Preimage::EvenChars,
+        _ if cmds.iter().all(|c| is_substitution(c)) => {
+            let mut sources = vec![Vec::new(); 256];
+            for c in 0..=255u8 {
+                match rules::mutate(&[c], rules) {
+                    Some(o) if o.len() == 1 => sources[o[0] as usize].push(c),
+                    _ => return None,
+                }
+            }
+            Preimage::CharMap(sources)
+        }
+        _ => {
+            let mut inverted = Vec::new();
+            let mut min_len = 1;
+            for c in cmds.iter().rev() {
+                let (ic, ml) = invert_command(c)?;
+                inverted.push(Rule::Command(ic));
+                min_len = min_len.max(ml);
+            }
+            return Some(Inverse {
+                preimage: Preimage::Apply(inverted),
+                min_len,
+            });
+        }
+    };
+    Some(Inverse {
+        preimage,
+        min_len: 1,
+    })
+}
+
+// calls f on every combination of characters
+fn char_products<F: FnMut(&[u8])>(
+    sources: &[Vec<u8>],
+    fragment: &[u8],
+    cur: &mut Vec<u8>,
+    f: &mut F,
+) {
+    let pos = cur.len();
+    if pos == fragment.len() {
+        f(cur);
+        return;
+    }
+    for &c in &sources[fragment[pos] as usize] {
+        cur.push(c);
+        char_products(sources, fragment, cur, f);
+        cur.pop();
+    }
+}
+
+impl Inverse {
+    // amount of words looked up for the fragment, saturating
+    fn preimages_count(&self, fragment: &[u8]) -> usize {
+        match &self.preimage {
+            Preimage::CharMap(sources) => fragment
+                .iter()
+                .fold(1, |acc, &c| acc.saturating_mul(sources[c as usize].len())),
+            _ => 1,
+        }
+    }
+
+    // calls f on all the words of the wordlist that might be transformed into the fragment
+    fn preimages<F: FnMut(&[u8])>(
+        &self,
+        fragment: &[u8],
+        index: &WordIndex,
+        wordlist: &[Vec<u8>],
+        mut f: F,
+    ) {
+        let mut check = |w: &[u8]| {
+            if index.contains(wordlist, w) {
+                f(w)
+            }
+        };
+        let half = fragment.len() / 2;
+        match &self.preimage {
+            Preimage::Apply(rls) => {
+                if let Some(w) = rules::mutate(fragment, rls) {
+                    check(&w)
+                }
+            }
+            Preimage::FirstHalf => {
+                if fragment.len().is_multiple_of(2) {
+                    check(&fragment[..half])
+                }
+            }
+            Preimage::EvenChars => {
+                if fragment.len().is_multiple_of(2) {
+                    let w: Vec<u8> = fragment.iter().step_by(2).cloned().collect();
+                    check(&w)
+                }
+            }
+            Preimage::CharMap(sources) => {
+                let mut cur = Vec::with_capacity(fragment.len());
+                char_products(sources, fragment, &mut cur, &mut check)
+            }
+            Preimage::CaseFold => {
+                if let Some(ids) = index.folded.get(&fragment.to_ascii_lowercase()) {
+                    for &i in ids {
+                        f(&wordlist[i as usize])
+                    }
+                }
+            }
+        }
+    }
+}
+
+// gives the same results as matcher::worker_logic
+pub fn inverse_worker_logic(
+    rules: Vec<Rule>,
+    inverse: &Inverse,
+    index: &WordIndex,
+    wordlist: &[Vec<u8>],
+    aclear: &CleartextMap,
+    cutoff: usize,
+) -> WorkerHits {
+    let mut hits: HashMap<Vec<Rule>, BTreeSet<u64>> = HashMap::new();
+    let program = compiled::compile(&rules);
+    let mut executor = compiled::Executor::new();
+    let mut oversized = HashMap::new();
+    for (fragment, matches) in aclear {
+        if inverse.preimages_count(fragment) > MAX_PREIMAGES {
+            oversized.insert(&fragment[..], matches);
+            continue;
+        }
+        let mut found = false;
+        inverse.preimages(fragment, index, wordlist, |w| {
+            // the preimages are only candidates, as some rules are not defined for all words
+            if !found && executor.run(&program, w) == Some(&fragment[..]) {
+                found = true;
+            }
+        });
+        if found {
+            record_matches(&mut hits, &rules, matches);
+        }
+    }
+    if !oversized.is_empty() {
+        for word in wordlist {
+            if let Some(matches) = executor.run(&program, word).and_then(|m| oversized.get(m)) {
+                record_matches(&mut hits, &rules, matches);
+            }
+        }
+    }
+    (
+        finish_hits(hits, cutoff),
+        index.count_min_len(inverse.min_len),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cleartexts;
+    use crate::matcher::{worker_logic, Extras};
+
+    fn conv(i: &str) -> Vec<u8> {
+        i.as_bytes().to_vec()
+    }
+
+    #[test]
+    fn same_as_forward() {
+        let wordlist: Vec<Vec<u8>> = [
+            "password", "Password", "PASSWORD", "pass", "qwerty", "abcabc", "abc", "hello", "",
+            "x", "monkey", "dragon", "s3cret", "secret", "aaaa",
+        ]
+        .iter()
+        .map(|w| conv(w))
+        .collect();
+        let clears = [
+            "password123",
+            "drowssap",
+            "PASSWORD!",
+            "Password1",
+            "passpass",
+            "1passwordssap",
+            "asswordp",
+            "dpasswor",
+            "p4ssword",
+            "s3cr3t",
+            "53cr3t!",
+            "abcabcabcabc",
+            "hheelllloo",
+            "aPssword",
+            "passwrod",
+            "mONKEY",
+            "MONKEY12",
+            "Dragon2020",
+            "qwertyytrewq",
+            "4444",
+        ];
+        let mut aclear = HashMap::new();
+        for (i, c) in clears.iter().enumerate() {
+            cleartexts::process_line(&mut aclear, i as u64, &conv(c), 3);
+        }
+        let index = WordIndex::new(&wordlist);
+        let mut inverted = 0;
+        for rs in rules::genmutate() {
+            if let Some(inv) = invert(&rs) {
+                inverted += 1;
+                let forward = worker_logic(rs.clone(), &wordlist, &aclear, 1, &Extras::default());
+                let backward =
+                    inverse_worker_logic(rs.clone(), &inv, &index, &wordlist, &aclear, 1);
+                assert_eq!(forward, backward, "rule {:?}", rs);
+            }
+        }
+        assert!(inverted > 30);
+    }
+
+    #[test]
+    fn oversized_fragments() {
+        let wordlist: Vec<Vec<u8>> = ["4a4a4a4a4a4a4a4a", "aaaaaaaaaaaaaaaa", "AAAA"]
+            .iter()
+            .map(|w| conv(w))
+            .collect();
+        let mut aclear = HashMap::new();
+        for (i, c) in ["4444444444444444", "4444"].iter().enumerate() {
+            cleartexts::process_line(&mut aclear, i as u64, &conv(c), 4);
+        }
+        let index = WordIndex::new(&wordlist);
+        let rs = crate::rulefile::parse_rule(b"sa4sA4", false).unwrap();
+        let inv = invert(&rs).unwrap();
+        // 3 sources for each character
+        assert!(inv.preimages_count(b"4444444444444444") > MAX_PREIMAGES);
+        assert_eq!(inv.preimages_count(b"4444"), 81);
+        let forward = worker_logic(rs.clone(), &wordlist, &aclear, 1, &Extras::default());
+        let backward = inverse_worker_logic(rs, &inv, &index, &wordlist, &aclear, 1);
+        assert_eq!(forward, backward);
+        assert!(!backward.0.is_empty());
+    }
+}
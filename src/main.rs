@@ -1,33 +1,201 @@
-use crate::cleartexts::CleartextMap;
 use indicatif::ProgressBar;
-use std::cmp::Ordering;
+use signal_hook::consts::{SIGINT, SIGTERM};
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{self, BufRead};
 use std::iter::FromIterator;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
+mod baseline;
+mod bruteforce;
+mod casefold;
 mod cleartexts;
+mod compiled;
+mod dedup;
+mod edits;
+mod folds;
+mod gates;
+mod hitsfile;
+mod holdout;
+mod hybrid;
+mod inverse;
 mod matcher;
+mod merge;
+mod output;
+mod policy;
+mod rejects;
+mod rulefile;
 mod rules;
+mod selection;
+mod single;
+mod substitutions;
 
 lazy_static::lazy_static! {
     static ref CONVS: rules::Converts = rules::make_converts();
+    // set by SIGINT/SIGTERM, a second signal terminates the process
+    static ref INTERRUPTED: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
 }
 
-fn worker_thread(
-    r: crossbeam::channel::Receiver<Vec<rules::Rule>>,
-    s: crossbeam::channel::Sender<HashMap<Vec<rules::Rule>, Vec<u64>>>,
-    alines: Arc<Vec<Vec<u8>>>,
-    aclear: Arc<CleartextMap>,
+fn interrupted() -> bool {
+    INTERRUPTED.load(atomic::Ordering::Relaxed)
+}
+
+fn handle_interruptions() {
+    for sig in &[SIGINT, SIGTERM] {
+        // the conditional shutdown must be registered first, so that it only fires on the second signal
+        signal_hook::flag::register_conditional_shutdown(*sig, 1, INTERRUPTED.clone()).unwrap();
+        signal_hook::flag::register(*sig, INTERRUPTED.clone()).unwrap();
+    }
+}
+
+// rules are sent and received along with their index in the rule list
+// the result of the evaluation of the rules
+struct Evaluation {
+    hits: selection::HitMap,
+    evaluated: usize,
     cutoff: usize,
-) {
-    while let Ok(rules) = r.recv() {
-        let hits = matcher::worker_logic(rules, &alines, &aclear, cutoff);
-        s.send(hits).unwrap();
+}
+
+// evaluates the rules on the worker threads, pruning the hits to --max-hits and saving the
+// progress to the checkpoint, if any; evaluate gives the hits of a rule for the current cutoff
+fn evaluate_rules<F>(
+    opt: &Options,
+    allrules: Vec<Vec<rules::Rule>>,
+    checkpoint: Option<(&Path, hitsfile::RunParameters)>,
+    evaluate: F,
+) -> Evaluation
+where
+    F: Fn(Vec<rules::Rule>, usize) -> matcher::WorkerHits + Send + Sync + 'static,
+{
+    // create channels for each threads
+    let (send_rule, recv_rule) = crossbeam::channel::bounded::<(usize, Vec<rules::Rule>)>(128);
+    let (send_hits, recv_hits) = crossbeam::channel::bounded(128);
+
+    // raised when the hits get pruned
+    let cutoff = Arc::new(AtomicUsize::new(opt.cutoff));
+    let evaluate = Arc::new(evaluate);
+    for _ in 0..opt.threads {
+        let rcv = recv_rule.clone();
+        let snd = send_hits.clone();
+        let c_cutoff = cutoff.clone();
+        let c_evaluate = evaluate.clone();
+        thread::spawn(move || {
+            while let Ok((idx, rules)) = rcv.recv() {
+                // drain the queue without doing anything when interrupted
+                if interrupted() {
+                    continue;
+                }
+                let hits = c_evaluate(rules, c_cutoff.load(atomic::Ordering::Relaxed));
+                snd.send((idx, hits)).unwrap();
+            }
+        });
+    }
+    // the hits channel gets closed when all workers are done
+    drop(send_hits);
+
+    let rules_count = allrules.len();
+    let mut done = vec![false; rules_count];
+    let mut hits: selection::HitMap = HashMap::new();
+    // --resume without --checkpoint is refused in main
+    if let Some((path, parameters)) = checkpoint.as_ref().filter(|_| opt.resume) {
+        let ck = exit_on_error(
+            hitsfile::load_checkpoint(path)
+                .map_err(|e| format!("could not load checkpoint {:?}: {}", path, e)),
+        );
+        if ck.parameters != *parameters {
+            eprintln!(
+                "checkpoint parameters {:?} do not match the current run {:?}",
+                ck.parameters, parameters
+            );
+            std::process::exit(1);
+        }
+        for idx in ck.done {
+            done[idx as usize] = true;
+        }
+        cutoff.store(ck.threshold as usize, atomic::Ordering::Relaxed);
+        hits = ck.hits;
+    }
+    let todo: Vec<usize> = (0..rules_count).filter(|i| !done[*i]).collect();
+    let mut evaluated = rules_count - todo.len();
+
+    // from now on, interrupting the program stops the evaluation, and the selection runs on what
+    // has been evaluated so far
+    handle_interruptions();
+
+    // send all rules in the queue
+    thread::spawn(move || {
+        for idx in todo {
+            if interrupted() {
+                break;
+            }
+            send_rule.send((idx, allrules[idx].clone())).unwrap();
+        }
+    });
+
+    // receive all results
+    let progress = ProgressBar::new(rules_count as u64);
+    progress
+        .set_style(indicatif::ProgressStyle::default_bar().template(
+            "[ETA: {eta_precise}] {bar:60.cyan/blue} {pos}/{len} - {msg} rules retained",
+        ));
+    progress.set_position(evaluated as u64);
+    let save = |done: &[bool], hits: &selection::HitMap| {
+        if let Some((path, parameters)) = &checkpoint {
+            hitsfile::save_checkpoint(
+                path,
+                parameters,
+                cutoff.load(atomic::Ordering::Relaxed) as u64,
+                done,
+                hits,
+            )
+            .unwrap_or_else(|e| {
+                eprintln!("could not save checkpoint {:?}: {}", path, e);
+                std::process::exit(1)
+            });
+        }
+    };
+    let checkpoint_interval = Duration::from_secs(opt.checkpoint_interval);
+    let mut last_checkpoint = Instant::now();
+    while let Ok((idx, (cur_hits, candidates))) = recv_hits.recv() {
+        progress.set_message(hits.len().to_string().as_str());
+        progress.inc(1);
+        hits.extend(cur_hits.into_iter().map(|(k, cracked)| {
+            (
+                k,
+                selection::RuleHits {
+                    candidates,
+                    cracked,
+                },
+            )
+        }));
+        done[idx] = true;
+        evaluated += 1;
+        if let Some(max_hits) = opt.max_hits {
+            if hits.len() > max_hits {
+                // prune a bit more than necessary, so that this does not happen for every rule
+                let threshold = selection::prune(&mut hits, max_hits * 3 / 4);
+                cutoff.fetch_max(threshold, atomic::Ordering::Relaxed);
+            }
+        }
+        if last_checkpoint.elapsed() >= checkpoint_interval {
+            save(&done, &hits);
+            last_checkpoint = Instant::now();
+        }
+    }
+    save(&done, &hits);
+    // the selection can be interrupted independently
+    INTERRUPTED.store(false, atomic::Ordering::Relaxed);
+    progress.finish();
+
+    Evaluation {
+        hits,
+        evaluated,
+        cutoff: cutoff.load(atomic::Ordering::Relaxed),
     }
 }
 
@@ -43,41 +211,36 @@ fn read_wordlist(wordlist: &Path) -> Vec<Vec<u8>> {
     all_lines
 }
 
-fn shorter_rules(a: &[rules::Rule], b: &[rules::Rule]) -> bool {
-    let la = rules::show_rules(a, false).map(|x| x.len());
-    let lb = rules::show_rules(b, false).map(|x| x.len());
-    la < lb || (la == lb && a < b)
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (digits, mult) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        Some('T') => (&s[..s.len() - 1], 1 << 40),
+        _ => (s, 1 << 20),
+    };
+    digits
+        .parse::<u64>()
+        .map(|n| n * mult)
+        .map_err(|e| format!("invalid size {}: {}", s, e))
 }
 
-fn sub_set(a: &[u64], b: &[u64]) -> Vec<u64> {
-    let mut o = Vec::new();
-    let mut ai = a.iter();
-    let mut bi = b.iter();
-    let mut ma = ai.next();
-    let mut mb = bi.next();
-    loop {
-        match (ma, mb) {
-            (Some(cura), Some(curb)) => match cura.cmp(curb) {
-                Ordering::Equal => {
-                    ma = ai.next();
-                    mb = bi.next();
-                }
-                Ordering::Greater => {
-                    mb = bi.next();
-                }
-                Ordering::Less => {
-                    o.push(*cura);
-                    ma = ai.next();
-                }
-            },
-            (None, _) => break,
-            (Some(cura), None) => {
-                o.push(*cura);
-                ma = ai.next();
-            }
-        }
-    }
-    o
+// peak resident memory, in bytes
+fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kb * 1024)
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Runs the rule selection on hits saved with --save-hits, instead of computing them
+    Select {
+        /// Saved hits path
+        #[structopt(parse(from_os_str))]
+        hits: PathBuf,
+    },
 }
 
 #[derive(Debug, StructOpt)]
@@ -85,10 +248,18 @@ fn sub_set(a: &[u64], b: &[u64]) -> Vec<u64> {
 struct Options {
     /// Training wordlist path
     #[structopt(long = "wordlist", short = "w", parse(from_os_str))]
-    wordlist: PathBuf,
-    /// Training clear text passwords
-    #[structopt(long = "cleartexts", short = "p", parse(from_os_str))]
-    cleartexts: PathBuf,
+    wordlist: Option<PathBuf>,
+    /// Training clear text passwords, can be repeated to train on several corpora
+    #[structopt(
+        long = "cleartexts",
+        short = "p",
+        number_of_values = 1,
+        parse(from_os_str)
+    )]
+    cleartexts: Vec<PathBuf>,
+    /// Only keep the rules that pass the cutoff in at least M of the cleartexts corpora
+    #[structopt(long = "min-corpora", name = "CORPORA")]
+    min_corpora: Option<usize>,
     /// Minimum amount of passwords cracked for a rule to be kept
     #[structopt(long = "cutoff", short = "n", name = "LEN", default_value("50"))]
     cutoff: usize,
@@ -107,149 +278,863 @@ struct Options {
     /// Print statistics in the rule output
     #[structopt(long = "details")]
     details: bool,
+    /// Selection objective: cracks (raw amount of cracks), efficiency (cracks per candidate) or balanced (sum of the fractions of each corpus cracked)
+    #[structopt(long = "objective", default_value("cracks"))]
+    objective: selection::Objective,
+    /// Instead of the rules, suggest hashcat hybrid attacks (-a 6 and -a 7) for the learned prefixes and suffixes, writing their masks to PREFIX-N.hcmask
+    #[structopt(long = "hybrid", name = "PREFIX")]
+    hybrid: Option<String>,
+    /// Fraction of the cleartexts kept out of the training, on which the selected rules are then evaluated
+    #[structopt(long = "holdout", name = "FRACTION")]
+    holdout: Option<f64>,
+    /// Cleartexts on which the selected rules are evaluated, instead of a fraction of the training cleartexts
+    #[structopt(long = "test-cleartexts", name = "TESTFILE", parse(from_os_str))]
+    test_cleartexts: Option<PathBuf>,
+    /// Also run the selection on K disjoint slices of the cleartexts, and annotate the rules with the amount of slices selecting them and the variation of their gain (--details)
+    #[structopt(long = "folds", name = "FOLDS")]
+    folds: Option<usize>,
+    /// Merge the selected rules that only differ by an affix character into JtR preprocessor classes, such as $[0-9]
+    #[structopt(long = "merge-classes")]
+    merge_classes: bool,
+    /// Relative cost of hashing a candidate, compared to generating it (efficiency objective)
+    #[structopt(long = "hash-cost", name = "COST", default_value("1"))]
+    hash_cost: f64,
+    /// Maximum memory for the fragment index, in MB or with a K/M/G/T suffix
+    #[structopt(long = "max-memory", name = "MEMORY", parse(try_from_str = parse_size))]
+    max_memory: Option<u64>,
+    /// What to do when the fragment index does not fit: refuse, minsize (raise it) or subsample (the cleartexts)
+    #[structopt(long = "memory-strategy", name = "STRATEGY", default_value("refuse"))]
+    memory_strategy: cleartexts::MemoryStrategy,
+    /// Also learn strings of up to MAXLEN characters inserted inside the words (slower)
+    #[structopt(long = "insertions", name = "MAXLEN", default_value("0"))]
+    insertions: usize,
+    /// Also learn the case changes turning the words into fragments that only differ by case (T chains, c, u or l)
+    #[structopt(long = "casefold")]
+    casefold: bool,
+    /// Mine the PAIRS most frequent substitutions of a letter by a digit or a symbol from the corpus, and add the corresponding rules
+    #[structopt(long = "substitutions", name = "PAIRS", default_value("0"))]
+    substitutions: usize,
+    /// Also learn the characters overstruck or deleted at a single position of the words (o and D rules, slower)
+    #[structopt(long = "edits")]
+    edits: bool,
     /// Preallocate memory for fragments : should be faster but might be wasteful
     #[structopt(long = "preallocate")]
     preallocate: bool,
+    /// Rule file (JtR or hashcat, depending on --hashcat) whose rules are used instead of the generated rules, the learned prefixes and suffixes being attached to them
+    #[structopt(long = "base-rules", name = "BASERULES", parse(from_os_str))]
+    base_rules: Option<PathBuf>,
+    /// Rule file of the attack that is already run with the wordlist, the cleartexts it cracks are not used for training
+    #[structopt(long = "baseline", name = "BASELINE", parse(from_os_str))]
+    baseline: Option<PathBuf>,
+    /// Hashcat mask (?l, ?u, ?d, ?s, ?a, ?h, ?H or ?b charsets) of a planned brute-force attack, the cleartexts it covers are not used for training, can be repeated
+    #[structopt(long = "brute-force-mask", name = "MASK", number_of_values = 1)]
+    brute_force_masks: Vec<bruteforce::Mask>,
+    /// Length up to which all the cleartexts are brute forced, and not used for training
+    #[structopt(long = "brute-force-length", name = "BFLEN")]
+    brute_force_length: Option<usize>,
+    /// Password policy of the target, such as minlen=8,maxlen=64,classes=3,require=d (l, u, d and s classes): the other cleartexts are not used for training, and the JtR rules reject the candidates that do not comply
+    #[structopt(long = "policy", name = "POLICY")]
+    policy: Option<policy::Policy>,
+    /// Prefix the JtR rules with the <N and >N rejects skipping the words whose lengths produce no cracks, losing at most this fraction of the cracks of each rule
+    #[structopt(long = "length-gates", name = "LOSS")]
+    length_gates: Option<f64>,
+    /// Learn single mode rules from login:password[:extra fields] records, the rules being run on the login and the words of the extra fields of each account, and only matched with its password
+    #[structopt(long = "single", name = "ACCOUNTS", parse(from_os_str))]
+    single: Option<PathBuf>,
+    /// Rule file (JtR or hashcat, depending on --hashcat) whose rules are added to the generated rules, JtR preprocessor ranges such as s[a-z][0-9] being expanded
+    #[structopt(long = "template", name = "TEMPLATE", parse(from_os_str))]
+    template: Option<PathBuf>,
+    /// Only use the rules of the template, instead of adding them to the generated rules
+    #[structopt(long = "template-only")]
+    template_only: bool,
+    /// Skip the rules matching this pattern (JtR preprocessor ranges are expanded), can be repeated
+    #[structopt(long = "exclude", name = "PATTERN", number_of_values = 1)]
+    exclude: Vec<String>,
+    /// Keep rules that behave like another, shorter, rule on the probe words
+    #[structopt(long = "no-dedup")]
+    no_dedup: bool,
+    /// Never evaluate rules backwards from the cleartexts, even when the wordlist is larger than the fragment index
+    #[structopt(long = "no-inverse")]
+    no_inverse: bool,
+    /// Save the computed hits to this file, so that the selection can be run again with the select command
+    #[structopt(long = "save-hits", name = "HITSFILE", parse(from_os_str))]
+    save_hits: Option<PathBuf>,
+    /// Maximum amount of rules kept in memory during the evaluation, the cutoff gets raised to fit
+    #[structopt(long = "max-hits", name = "MAXHITS")]
+    max_hits: Option<usize>,
+    /// Periodically save the progress of the rule evaluation to this file
+    #[structopt(long = "checkpoint", name = "CHECKPOINT", parse(from_os_str))]
+    checkpoint: Option<PathBuf>,
+    /// Interval between checkpoints, in seconds
+    #[structopt(long = "checkpoint-interval", name = "SECS", default_value("600"))]
+    checkpoint_interval: u64,
+    /// Resume the rule evaluation from the checkpoint file
+    #[structopt(long = "resume")]
+    resume: bool,
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
 }
 
-fn main() {
-    let opt = Options::from_args();
-    let allrules = rules::genmutate()
-        .into_iter()
-        .filter(|rs| {
-            if opt.hashcat {
-                rs.iter().all(rules::hashcat_rule)
-            } else {
-                rs.iter().all(rules::john_rule)
+fn missing(name: &str) -> ! {
+    structopt::clap::Error::with_description(
+        &format!(
+            "The following required argument was not provided: --{}",
+            name
+        ),
+        structopt::clap::ErrorKind::MissingRequiredArgument,
+    )
+    .exit()
+}
+
+fn required<'a>(o: &'a Option<PathBuf>, name: &str) -> &'a Path {
+    match o {
+        Some(x) => x,
+        None => missing(name),
+    }
+}
+
+fn exit_on_error<T>(r: Result<T, String>) -> T {
+    r.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    })
+}
+
+fn read_base_rules(path: &Path, hashcat: bool) -> rulefile::RuleFile {
+    // hashcat rule files are not preprocessed, as [ and ] are regular commands
+    let base = exit_on_error(rulefile::read_rules(path, hashcat, !hashcat));
+    for e in &base.errors {
+        eprintln!("{} (skipped)", e);
+    }
+    base
+}
+
+// drops the rules that the output dialect does not support, and the excluded ones, returning the
+// amount of excluded rules
+fn filter_rules(
+    opt: &Options,
+    allrules: &mut Vec<Vec<rules::Rule>>,
+    excluded: &HashSet<Vec<rules::Rule>>,
+) -> usize {
+    allrules.retain(|rs| {
+        if opt.hashcat {
+            rs.iter().all(rules::hashcat_rule)
+        } else {
+            rs.iter().all(rules::john_rule)
+        }
+    });
+    let before = allrules.len();
+    allrules.retain(|rs| !excluded.contains(rs));
+    before - allrules.len()
+}
+
+// the rules to evaluate, and the excluded ones
+fn candidate_rules(
+    opt: &Options,
+    base: Option<&rulefile::RuleFile>,
+) -> (Vec<Vec<rules::Rule>>, HashSet<Vec<rules::Rule>>) {
+    let mut allrules = if let Some(base) = base {
+        let mut seen = HashSet::new();
+        base.rules
+            .iter()
+            .filter(|(_, rs)| seen.insert(rs.clone()))
+            .map(|(_, rs)| rs.clone())
+            .collect()
+    } else if opt.template_only {
+        required(&opt.template, "template");
+        Vec::new()
+    } else {
+        rules::genmutate()
+    };
+    if let Some(path) = &opt.template {
+        let template = exit_on_error(rulefile::read_rules(path, opt.hashcat, !opt.hashcat));
+        if let Some(e) = template.errors.first() {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        let known: HashSet<Vec<rules::Rule>> = allrules.iter().cloned().collect();
+        let mut added: HashSet<Vec<rules::Rule>> = HashSet::new();
+        for (_, rs) in template.rules {
+            if !known.contains(&rs) && added.insert(rs.clone()) {
+                allrules.push(rs);
             }
-        })
-        .collect::<Vec<_>>();
+        }
+        eprintln!("{} rules added from the template", added.len());
+    }
+    let mut excluded: HashSet<Vec<rules::Rule>> = HashSet::new();
+    for pattern in &opt.exclude {
+        let rs = rulefile::parse_line(pattern.as_bytes(), opt.hashcat, true)
+            .map_err(|e| format!("invalid exclusion pattern {}: {}", pattern, e));
+        excluded.extend(exit_on_error(rs));
+    }
+    let removed = filter_rules(opt, &mut allrules, &excluded);
+    if !excluded.is_empty() {
+        eprintln!("{} rules excluded", removed);
+    }
+    (allrules, excluded)
+}
+
+// removes the cleartexts covered by the planned brute-force attacks, returning their ids
+fn remove_brute_forced(
+    opt: &Options,
+    what: &str,
+    lines: &mut HashMap<u64, Vec<u8>>,
+) -> HashSet<u64> {
+    if opt.brute_force_masks.is_empty() && opt.brute_force_length.is_none() {
+        return HashSet::new();
+    }
+    let covered = bruteforce::covered(
+        lines,
+        &opt.brute_force_masks,
+        opt.brute_force_length.unwrap_or(0),
+    );
+    eprintln!("{} {} covered by brute force removed", covered.len(), what);
+    lines.retain(|id, _| !covered.contains(id));
+    covered
+}
+
+// removes the cleartexts the target system would not have accepted, returning their ids
+fn remove_non_compliant(
+    opt: &Options,
+    what: &str,
+    lines: &mut HashMap<u64, Vec<u8>>,
+) -> HashSet<u64> {
+    let policy = match &opt.policy {
+        None => return HashSet::new(),
+        Some(p) => p,
+    };
+    let rejected: HashSet<u64> = lines
+        .iter()
+        .filter(|(_, l)| !policy.accepts(l))
+        .map(|(i, _)| *i)
+        .collect();
+    eprintln!(
+        "{} {} not complying with the policy removed",
+        rejected.len(),
+        what
+    );
+    lines.retain(|id, _| !rejected.contains(id));
+    rejected
+}
+
+// removes the cleartexts cracked by the baseline attack, returning their ids
+fn remove_baseline(
+    opt: &Options,
+    what: &str,
+    wordlist: &[Vec<u8>],
+    lines: &mut HashMap<u64, Vec<u8>>,
+) -> HashSet<u64> {
+    let path = match &opt.baseline {
+        None => return HashSet::new(),
+        Some(p) => p,
+    };
+    let file = read_base_rules(path, opt.hashcat);
+    let rules: Vec<Vec<rules::Rule>> = file.rules.into_iter().map(|(_, rs)| rs).collect();
+    let cracked = baseline::cracked(&rules, wordlist, lines, opt.threads as usize);
+    println!(
+        "# baseline: {} rules, {}/{} {} cracked ({:.2}%)",
+        rules.len(),
+        cracked.len(),
+        lines.len(),
+        what,
+        cracked.len() as f64 * 100.0 / lines.len().max(1) as f64
+    );
+    lines.retain(|id, _| !cracked.contains(id));
+    cracked
+}
+
+// amount of cracked passwords in each corpus
+fn corpus_counts(cracked: &[u64], corpora: usize) -> Vec<usize> {
+    let mut counts = vec![0; corpora];
+    for id in cracked {
+        counts[cleartexts::corpus(*id)] += 1;
+    }
+    counts
+}
+
+fn dedup_rules(
+    opt: &Options,
+    allrules: Vec<Vec<rules::Rule>>,
+    words: &[Vec<u8>],
+) -> Vec<Vec<rules::Rule>> {
+    if opt.no_dedup {
+        return allrules;
+    }
+    let probes = dedup::probe_words(words, 1000);
+    let (deduped, removed) = dedup::dedup(allrules, &probes);
+    eprintln!(
+        "{} equivalent rules removed, {} rules to evaluate",
+        removed,
+        deduped.len()
+    );
+    deduped
+}
+
+// the test cleartexts are filtered like the training ones
+fn read_test_cleartexts(
+    opt: &Options,
+    path: &Path,
+    minsize: usize,
+    wordlist: &[Vec<u8>],
+) -> Vec<Vec<u8>> {
+    let mut lines = cleartexts::read(path, 0, minsize, &HashSet::from_iter(wordlist))
+        .unwrap_or_else(|e| panic!("could not read test cleartexts {:?}: {}", path, e));
+    remove_non_compliant(opt, "test cleartexts", &mut lines);
+    remove_brute_forced(opt, "test cleartexts", &mut lines);
+    remove_baseline(opt, "test cleartexts", wordlist, &mut lines);
+    let mut ids: Vec<u64> = lines.keys().cloned().collect();
+    ids.sort_unstable();
+    ids.iter().map(|i| lines.remove(i).unwrap()).collect()
+}
+
+fn compute_hits(
+    opt: &Options,
+    base: Option<&rulefile::RuleFile>,
+) -> (hitsfile::SavedHits, Option<holdout::TestSet>) {
+    let (allrules, excluded) = candidate_rules(opt, base);
 
     if opt.combinations != 1 {
         panic!("combos must be 1 for now");
     }
 
-    let vwordlist = read_wordlist(&opt.wordlist);
+    let vwordlist = read_wordlist(required(&opt.wordlist, "wordlist"));
     let swordlist = HashSet::from_iter(&vwordlist);
-    let (clearmap, _) =
-        cleartexts::process(opt.preallocate, &opt.cleartexts, opt.minsize, &swordlist).unwrap();
+    let mut minsize = opt.minsize;
+    if opt.cleartexts.is_empty() {
+        missing("cleartexts");
+    }
+    let mut clearlines = HashMap::new();
+    for (corpus, path) in opt.cleartexts.iter().enumerate() {
+        clearlines.extend(cleartexts::read(path, corpus, minsize, &swordlist).unwrap());
+    }
+    remove_non_compliant(opt, "cleartexts", &mut clearlines);
+    remove_brute_forced(opt, "cleartexts", &mut clearlines);
+    remove_baseline(opt, "cleartexts", &vwordlist, &mut clearlines);
+    let test_lines = match (&opt.test_cleartexts, opt.holdout) {
+        (Some(path), _) => Some(read_test_cleartexts(opt, path, minsize, &vwordlist)),
+        (None, Some(fraction)) => {
+            let test = holdout::split(&mut clearlines, fraction);
+            eprintln!(
+                "{} cleartexts held out, {} used for training",
+                test.len(),
+                clearlines.len()
+            );
+            Some(test)
+        }
+        (None, None) => None,
+    };
+    let extra = cleartexts::ExtraIndices {
+        casefold: opt.casefold,
+        edits: opt.edits,
+    };
+    let estimated = cleartexts::estimate_memory(&clearlines, minsize, extra);
+    eprintln!(
+        "estimated fragment index size: {}MB",
+        estimated / (1024 * 1024)
+    );
+    if let Some(max_memory) = opt.max_memory {
+        if let Err(e) = cleartexts::fit_memory(
+            &mut clearlines,
+            &mut minsize,
+            max_memory,
+            opt.memory_strategy,
+            extra,
+        ) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    let clearmap = cleartexts::index(opt.preallocate, &clearlines, minsize);
+
+    let mut allrules = allrules;
+    if opt.substitutions > 0 {
+        let mut subs = substitutions::mine(&vwordlist, &clearmap, 20000);
+        subs.truncate(opt.substitutions);
+        let known: HashSet<&Vec<rules::Rule>> = allrules.iter().collect();
+        let mut mined: Vec<Vec<rules::Rule>> = substitutions::substitution_rules(&subs)
+            .into_iter()
+            .filter(|r| !known.contains(r))
+            .collect();
+        filter_rules(opt, &mut mined, &excluded);
+        for sub in &subs {
+            eprintln!(
+                "substitution {} -> {}: {} cleartexts",
+                rules::show_char(sub.from),
+                rules::show_char(sub.to),
+                sub.cleartexts
+            );
+        }
+        eprintln!("{} substitution rules added", mined.len());
+        allrules.extend(mined);
+    }
+    let allrules = dedup_rules(opt, allrules, &vwordlist);
 
+    // invertible rules are evaluated from the fragments when there are fewer of them than words
+    // insertions, edits and case changes are only found when mutating the words
+    let windex = if !opt.no_inverse
+        && opt.insertions == 0
+        && !opt.edits
+        && !opt.casefold
+        && clearmap.len() < vwordlist.len()
+    {
+        eprintln!(
+            "{} fragments for {} words, evaluating invertible rules from the fragments",
+            clearmap.len(),
+            vwordlist.len()
+        );
+        Some(Arc::new(inverse::WordIndex::new(&vwordlist)))
+    } else {
+        None
+    };
+    let extras = Arc::new(matcher::Extras {
+        max_insertion: opt.insertions,
+        edits: if opt.edits {
+            Some(edits::EditIndex::new(&clearmap))
+        } else {
+            None
+        },
+        casefold: if opt.casefold {
+            Some(casefold::FoldedIndex::new(&clearmap))
+        } else {
+            None
+        },
+    });
     let arc_lines = Arc::new(vwordlist);
     let arc_clear = Arc::new(clearmap);
 
-    // create channels for each threads
-    let (send_rule, recv_rule) = crossbeam::channel::bounded(128);
-    let (send_hits, recv_hits) = crossbeam::channel::bounded(128);
-
-    let cutoff = opt.cutoff;
-    for _ in 0..opt.threads {
-        let rcv = recv_rule.clone();
-        let snd = send_hits.clone();
-        let c_lines = arc_lines.clone();
-        let c_clear = arc_clear.clone();
-        thread::spawn(move || worker_thread(rcv, snd, c_lines, c_clear, cutoff));
-    }
-
     let rules_count = allrules.len();
+    // the rules and inputs are only fingerprinted for the checkpoints
+    let checkpoint = opt.checkpoint.as_deref().map(|path| {
+        let parameters = hitsfile::RunParameters {
+            rules_count: rules_count as u64,
+            cutoff: opt.cutoff as u64,
+            minsize: minsize as u64,
+            hashcat: opt.hashcat,
+            cleartexts_count: clearlines.len() as u64,
+            max_insertion: opt.insertions as u64,
+            edits: opt.edits,
+            substitutions: opt.substitutions as u64,
+            casefold: opt.casefold,
+            rules_hash: hitsfile::rules_fingerprint(&allrules),
+            inputs_hash: hitsfile::inputs_fingerprint(&arc_lines, &clearlines),
+        };
+        (path, parameters)
+    });
 
-    // send all rules in the queue
-    thread::spawn(move || {
-        for rule in allrules.iter() {
-            send_rule.send(rule.clone()).unwrap();
+    let c_lines = arc_lines.clone();
+    let evaluation = evaluate_rules(opt, allrules, checkpoint, move |rules, cutoff| {
+        let inv = windex
+            .as_ref()
+            .and_then(|wi| inverse::invert(&rules).map(|inv| (wi, inv)));
+        match inv {
+            Some((wi, inv)) => {
+                inverse::inverse_worker_logic(rules, &inv, wi, &c_lines, &arc_clear, cutoff)
+            }
+            None => matcher::worker_logic(rules, &c_lines, &arc_clear, cutoff, &extras),
         }
     });
 
-    // receive all results
-    let mut hits: HashMap<Vec<rules::Rule>, Vec<u64>> = HashMap::new();
+    let saved = hitsfile::SavedHits {
+        wordlist_len: arc_lines.len() as u64,
+        rules_count: rules_count as u64,
+        evaluated: evaluation.evaluated as u64,
+        cutoff: evaluation.cutoff as u64,
+        cleartexts: clearlines,
+        hits: evaluation.hits,
+    };
+    (
+        saved,
+        test_lines.map(|lines| holdout::TestSet::new(arc_lines, lines)),
+    )
+}
 
-    let progress = ProgressBar::new(rules_count as u64);
-    progress
-        .set_style(indicatif::ProgressStyle::default_bar().template(
-            "[ETA: {eta_precise}] {bar:60.cyan/blue} {pos}/{len} - {msg} rules retained",
-        ));
-    let mut retained = 0;
-    for _ in 0..rules_count {
-        let cur_hits = recv_hits.recv().unwrap();
-        progress.set_message(retained.to_string().as_str());
-        progress.inc(1);
-        retained += cur_hits.len();
-        hits.extend(cur_hits);
+// the single mode counterpart of compute_hits, the candidates generated from the words of each
+// account being only matched with its password
+fn compute_single_hits(
+    opt: &Options,
+    base: Option<&rulefile::RuleFile>,
+    path: &Path,
+) -> hitsfile::SavedHits {
+    let (allrules, _) = candidate_rules(opt, base);
+    let (accounts, invalid) = single::read(path, opt.minsize)
+        .unwrap_or_else(|e| panic!("could not read accounts {:?}: {}", path, e));
+    if invalid > 0 {
+        eprintln!("{} invalid records skipped", invalid);
     }
-    progress.finish();
-    let mut hashcat_mode = opt.hashcat;
-
-    // greedy coverage
-    let mut last_set: Vec<u64> = Vec::new();
-    let mut total_cracked = 0;
-    while !hits.is_empty() {
-        let mut best_rules: Vec<rules::Rule> = vec![];
-        let mut best_count: usize = 0;
-        let mut best_set: Vec<u64> = Vec::new();
-        let mut to_remove: Vec<Vec<rules::Rule>> = Vec::new();
-        for im in hits.iter_mut() {
-            // early cutoff
-            if im.1.len() < opt.cutoff {
-                to_remove.push(im.0.clone());
-                continue;
+    let mut clearlines: HashMap<u64, Vec<u8>> = accounts
+        .iter()
+        .map(|(id, a)| (*id, a.password.clone()))
+        .collect();
+    remove_non_compliant(opt, "cleartexts", &mut clearlines);
+    remove_brute_forced(opt, "cleartexts", &mut clearlines);
+    let accounts: Vec<(u64, single::Account)> = accounts
+        .into_iter()
+        .filter(|(id, _)| clearlines.contains_key(id))
+        .collect();
+    let words: Vec<Vec<u8>> = accounts
+        .iter()
+        .flat_map(|(_, a)| a.words.iter().cloned())
+        .collect();
+    eprintln!("{} accounts, {} words", accounts.len(), words.len());
+    let allrules = dedup_rules(opt, allrules, &words);
+    let rules_count = allrules.len();
+    let minsize = opt.minsize;
+    let evaluation = evaluate_rules(opt, allrules, None, move |rules, cutoff| {
+        single::worker_logic(rules, &accounts, minsize, cutoff)
+    });
+
+    hitsfile::SavedHits {
+        wordlist_len: words.len() as u64,
+        rules_count: rules_count as u64,
+        evaluated: evaluation.evaluated as u64,
+        cutoff: evaluation.cutoff as u64,
+        cleartexts: clearlines,
+        hits: evaluation.hits,
+    }
+}
+
+fn main() {
+    let opt = Options::from_args();
+    if let Some(fraction) = opt.holdout {
+        if !(fraction > 0.0 && fraction < 1.0) {
+            eprintln!("the holdout fraction must be between 0 and 1");
+            std::process::exit(1);
+        }
+        if opt.cmd.is_some() {
+            eprintln!("--holdout is only used when computing the hits, use --test-cleartexts with the select command");
+            std::process::exit(1);
+        }
+    }
+    if opt.single.is_some()
+        && (opt.cmd.is_some()
+            || opt.wordlist.is_some()
+            || !opt.cleartexts.is_empty()
+            || opt.holdout.is_some()
+            || opt.test_cleartexts.is_some()
+            || opt.baseline.is_some()
+            || opt.length_gates.is_some()
+            || opt.checkpoint.is_some()
+            || opt.insertions > 0
+            || opt.edits
+            || opt.casefold
+            || opt.substitutions > 0)
+    {
+        eprintln!("--single only learns from the accounts, it can not be used with a wordlist, cleartexts, the select command, --holdout, --test-cleartexts, --baseline, --length-gates, --checkpoint, --insertions, --edits, --casefold or --substitutions");
+        std::process::exit(1);
+    }
+    if opt.resume && (opt.checkpoint.is_none() || opt.cmd.is_some()) {
+        eprintln!("--resume continues the evaluation saved with --checkpoint, it can not be used without it or with the select command");
+        std::process::exit(1);
+    }
+    if opt.max_hits.is_some_and(|n| n < 2) {
+        eprintln!("--max-hits must keep at least 2 rules");
+        std::process::exit(1);
+    }
+    if opt.max_hits.is_some() && !opt.objective.allows_pruning() {
+        eprintln!("--max-hits drops the rules cracking the fewest passwords, it can only be used with the cracks objective");
+        std::process::exit(1);
+    }
+    if opt.folds.is_some_and(|k| k < 2) {
+        eprintln!("at least 2 folds are needed");
+        std::process::exit(1);
+    }
+    if opt.merge_classes && opt.hashcat {
+        eprintln!(
+            "--merge-classes requires the JtR preprocessor, it can not be used with --hashcat"
+        );
+        std::process::exit(1);
+    }
+    if let Some(loss) = opt.length_gates {
+        if !(0.0..1.0).contains(&loss) {
+            eprintln!("the length gates loss must be between 0 and 1");
+            std::process::exit(1);
+        }
+        if opt.hashcat || opt.merge_classes {
+            eprintln!("--length-gates requires JtR rejects, it can not be used with --hashcat or --merge-classes");
+            std::process::exit(1);
+        }
+    }
+    let base = opt
+        .base_rules
+        .as_ref()
+        .map(|path| read_base_rules(path, opt.hashcat));
+
+    let (saved, mut testset) = match &opt.cmd {
+        Some(Command::Select { hits }) => {
+            let mut saved = hitsfile::load(hits)
+                .unwrap_or_else(|e| panic!("could not load hits from {:?}: {}", hits, e));
+            // the baseline and test cleartexts are cracked with the wordlist
+            let wordlist = if opt.baseline.is_some() || opt.test_cleartexts.is_some() {
+                read_wordlist(required(&opt.wordlist, "wordlist"))
+            } else {
+                Vec::new()
+            };
+            let mut cracked = remove_non_compliant(&opt, "cleartexts", &mut saved.cleartexts);
+            cracked.extend(remove_brute_forced(
+                &opt,
+                "cleartexts",
+                &mut saved.cleartexts,
+            ));
+            cracked.extend(remove_baseline(
+                &opt,
+                "cleartexts",
+                &wordlist,
+                &mut saved.cleartexts,
+            ));
+            if !cracked.is_empty() {
+                for rh in saved.hits.values_mut() {
+                    rh.cracked.retain(|id| !cracked.contains(id));
+                }
             }
-            *im.1 = sub_set(im.1, &last_set);
-            // deferred cutoff
-            if im.1.len() < opt.cutoff {
-                to_remove.push(im.0.clone());
-                continue;
+            let testset = opt.test_cleartexts.as_ref().map(|path| {
+                let lines = read_test_cleartexts(&opt, path, opt.minsize, &wordlist);
+                holdout::TestSet::new(Arc::new(wordlist), lines)
+            });
+            (saved, testset)
+        }
+        None => {
+            let (saved, testset) = match &opt.single {
+                Some(path) => (compute_single_hits(&opt, base.as_ref(), path), None),
+                None => compute_hits(&opt, base.as_ref()),
+            };
+            if let Some(path) = &opt.save_hits {
+                hitsfile::save(path, &saved)
+                    .unwrap_or_else(|e| panic!("could not save hits to {:?}: {}", path, e));
             }
-            let curlen = im.1.len();
-            if curlen > best_count || (curlen == best_count && shorter_rules(im.0, &best_rules)) {
-                best_count = curlen;
-                best_rules = im.0.clone();
-                best_set = im.1.clone();
+            (saved, testset)
+        }
+    };
+    if saved.evaluated < saved.rules_count {
+        println!(
+            "# PARTIAL RESULTS: evaluation interrupted after {}/{} rules ({:.1}%)",
+            saved.evaluated,
+            saved.rules_count,
+            saved.evaluated as f64 * 100.0 / saved.rules_count as f64
+        );
+    }
+    let mut cutoff = opt.cutoff;
+    if saved.cutoff as usize > cutoff {
+        cutoff = saved.cutoff as usize;
+        println!(
+            "# cutoff raised to {}, rules cracking less passwords were not kept",
+            cutoff
+        );
+    }
+    let mut hits = saved.hits;
+    if opt.hashcat {
+        // the saved hits might have been computed for JtR
+        hits.retain(|rs, _| rules::show_rules(rs, true).is_some());
+    }
+
+    if let Some(prefix) = &opt.hybrid {
+        let mut cracked = HashSet::new();
+        for (i, job) in hybrid::jobs(&hits, cutoff).iter().enumerate() {
+            let path = format!("{}-{}.hcmask", prefix, i + 1);
+            let masks: String = job.masks.iter().map(|m| m.mask.clone() + "\n").collect();
+            std::fs::write(&path, masks)
+                .unwrap_or_else(|e| panic!("could not write masks to {:?}: {}", path, e));
+            let before = cracked.len();
+            cracked.extend(job.cracked.iter().cloned());
+            println!(
+                "# [{} - {}] {} masks, keyspace {}",
+                cracked.len() - before,
+                cracked.len(),
+                job.masks.len(),
+                job.keyspace()
+            );
+            if opt.details {
+                for m in &job.masks {
+                    println!(
+                        "#   {}: {} cracks, {} candidates per word",
+                        m.mask,
+                        m.cracked.len(),
+                        m.keyspace
+                    );
+                }
             }
+            println!("{}", job.command(&path));
         }
-        for rm in to_remove {
-            hits.remove(&rm);
+        std::process::exit(0);
+    }
+
+    let corpus_sizes = cleartexts::corpus_sizes(&saved.cleartexts);
+    if let Some(m) = opt.min_corpora {
+        if m > corpus_sizes.len() {
+            eprintln!(
+                "--min-corpora is {}, but there are only {} corpora",
+                m,
+                corpus_sizes.len()
+            );
+            std::process::exit(1);
         }
-        hits.remove(&best_rules);
-        last_set = best_set;
-        let display = |rdesc, ttl| {
-            if opt.details {
-                println!("{} // [{} - {}]", rdesc, best_count, ttl);
-            } else {
-                println!("{}", rdesc);
+        let before = hits.len();
+        hits.retain(|_, rh| {
+            let counts = corpus_counts(&rh.cracked, corpus_sizes.len());
+            counts.iter().filter(|c| **c >= cutoff.max(1)).count() >= m
+        });
+        eprintln!(
+            "{} rules did not pass the cutoff in {} corpora",
+            before - hits.len(),
+            m
+        );
+    }
+    let scoring = selection::Scoring {
+        objective: opt.objective,
+        hash_cost: opt.hash_cost,
+        wordlist_len: saved.wordlist_len,
+        corpus_sizes: corpus_sizes.clone(),
+    };
+    let origins = base.as_ref().map(output::Origins::new);
+    let stability = opt
+        .folds
+        .map(|k| folds::stability(&hits, k, cutoff, &scoring));
+    let show_stats = |cracked: &[u64], total: usize, candidates: u64, rules: &[rules::Rule]| {
+        let count = cracked.len();
+        let mut stats = match opt.objective {
+            selection::Objective::Cracks | selection::Objective::Balanced => {
+                format!("{} - {}", count, total)
+            }
+            selection::Objective::Efficiency => {
+                format!("{} - {} - {} candidates", count, total, candidates)
             }
         };
-        if best_count > 0 {
-            total_cracked += best_count;
-            match rules::show_rules(&best_rules, hashcat_mode) {
-                Some(x) => display(x, total_cracked),
-                None => {
-                    if opt.hashcat {
-                        unreachable!(
-                            "should not happen : invalid rule to be displayed: {:?} hashcat_mode={}",
-                            &best_rules, hashcat_mode
-                        )
-                    } else {
-                        hashcat_mode = !hashcat_mode;
-
-                        if hashcat_mode {
-                            println!("!! hashcat logic ON");
-                        } else {
-                            println!("!! hashcat logic OFF");
-                        }
-                        match rules::show_rules(&best_rules, hashcat_mode) {
-                            Some(r) => display(r, total_cracked),
-                            None => {
-                                panic!("This rule is invalid with JtR & hashcat : {:?}", best_rules)
-                            }
-                        }
-                    }
+        if corpus_sizes.len() > 1 {
+            let counts: Vec<String> = corpus_counts(cracked, corpus_sizes.len())
+                .iter()
+                .map(|c| c.to_string())
+                .collect();
+            stats += &format!(" - corpora {}", counts.join("/"));
+        }
+        if let Some(line) = origins.as_ref().and_then(|o| o.line(rules)) {
+            stats += &format!(" - base rule line {}", line);
+        }
+        if let Some(st) = &stability {
+            stats += &format!(" - {}", st.show(rules));
+        }
+        stats
+    };
+    let train_len = saved.cleartexts.len();
+    let mut train_cracked = 0;
+    // the cumulative train and test crack rates
+    let mut curves = |rules: &[Vec<rules::Rule>], total: usize| match testset.as_mut() {
+        None => String::new(),
+        Some(ts) => {
+            let found: usize = rules.iter().map(|r| ts.evaluate(r)).sum();
+            train_cracked = total;
+            format!(
+                " - train {:.2}% - test {} - {} - {:.2}%",
+                total as f64 * 100.0 / train_len.max(1) as f64,
+                found,
+                ts.cracked,
+                ts.percent()
+            )
+        }
+    };
+    // the policy is enforced by the JtR rules
+    let rejects = match &opt.policy {
+        Some(_) if opt.hashcat => {
+            println!("# hashcat rules can not reject candidates, the policy must be enforced by filtering them");
+            Vec::new()
+        }
+        Some(policy) => policy.rejects(),
+        None => Vec::new(),
+    };
+    let reject_suffix = rules::show_rules(&rejects, false).unwrap();
+    // the words producing the cracks of the rules are found by running them again
+    let gate_words = opt
+        .length_gates
+        .map(|_| read_wordlist(required(&opt.wordlist, "wordlist")));
+    let train_lines = &saved.cleartexts;
+    // cracks, cracks kept, candidates, candidates saved
+    let mut gated = (0, 0, 0, 0);
+    let mut printer = output::RulePrinter::new(opt.hashcat, opt.details);
+    if opt.merge_classes {
+        // the hits of the rules that were not selected are needed to widen the classes
+        let all_hits = hits.clone();
+        let mut selected = Vec::new();
+        let mut min_efficiency = f64::MAX;
+        selection::greedy(&mut hits, cutoff, &scoring, |sel| {
+            if sel.candidates > 0 {
+                min_efficiency = min_efficiency.min(sel.count as f64 / sel.candidates as f64);
+            }
+            selected.push(sel.rules.clone());
+            !interrupted()
+        });
+        let mut cracked = HashSet::new();
+        for pattern in merge::merge(&selected, &all_hits, min_efficiency) {
+            let new: Vec<u64> = pattern
+                .cracked(&all_hits)
+                .into_iter()
+                .filter(|id| cracked.insert(*id))
+                .collect();
+            // the rules can be covered by earlier, wider, classes
+            if new.len() < cutoff.max(1) {
+                continue;
+            }
+            let stats = show_stats(
+                &new,
+                cracked.len(),
+                pattern.candidates(&all_hits),
+                &pattern.rules,
+            ) + &curves(&pattern.members(), cracked.len());
+            printer.print_with(
+                |hashcat_mode| pattern.show(hashcat_mode).map(|r| r + &reject_suffix),
+                &stats,
+            );
+        }
+    } else {
+        selection::greedy(&mut hits, cutoff, &scoring, |sel| {
+            let mut stats = show_stats(&sel.cracked, sel.total, sel.candidates, &sel.rules)
+                + &curves(std::slice::from_ref(&sel.rules), sel.total);
+            let gate_rejects = match (&gate_words, opt.length_gates) {
+                (Some(words), Some(loss)) => {
+                    let g = gates::gate(&sel.rules, words, train_lines, &sel.cracked, loss);
+                    gated.0 += g.cracks;
+                    gated.1 += g.kept;
+                    gated.2 += g.candidates;
+                    gated.3 += g.saved;
+                    stats += &format!(
+                        " - gates kept {}/{}, saved {}/{} candidates",
+                        g.kept, g.cracks, g.saved, g.candidates
+                    );
+                    g.rejects()
                 }
+                _ => Vec::new(),
             };
-            // do not print the final loop, where 'hits' is empty and nothing was found!
-        }
+            printer.print(
+                &[gate_rejects.as_slice(), &sel.rules, &rejects].concat(),
+                &stats,
+            );
+            !interrupted()
+        });
     }
-
-    if hashcat_mode && !opt.hashcat {
-        println!("!! hashcat logic OFF");
+    printer.finish();
+    if gate_words.is_some() {
+        println!(
+            "# length gates: {}/{} cracks kept, {}/{} candidates saved ({:.2}%)",
+            gated.1,
+            gated.0,
+            gated.3,
+            gated.2,
+            gated.3 as f64 * 100.0 / gated.2.max(1) as f64
+        );
+    }
+    if let Some(ts) = &testset {
+        println!(
+            "# train: {}/{} cracked ({:.2}%), test: {}/{} cracked ({:.2}%)",
+            train_cracked,
+            train_len,
+            train_cracked as f64 * 100.0 / train_len.max(1) as f64,
+            ts.cracked,
+            ts.total,
+            ts.percent()
+        );
+    }
+    if interrupted() {
+        println!("# PARTIAL RESULTS: selection interrupted");
+    }
+    if let Some(peak) = peak_memory() {
+        eprintln!("peak memory usage: {}MB", peak / (1024 * 1024));
     }
 
     // without this, it takes a long time to free the large "hits" hashmap
@@ -1,56 +1,123 @@
-use crate::cleartexts::CleartextMap;
-use smallvec::ToSmallVec;
+use crate::cleartexts::{CleartextInfo, CleartextMap};
 use std::collections::BTreeSet;
 use std::collections::HashMap;
 
+use crate::casefold::FoldedIndex;
+use crate::compiled;
+use crate::edits::EditIndex;
 use crate::rules;
 
-pub fn worker_logic(
-    rules: Vec<rules::Rule>,
-    wordlist: &[Vec<u8>],
+// hits of a rule, and the amount of candidates it generated on the wordlist
+pub type WorkerHits = (HashMap<Vec<rules::Rule>, Vec<u64>>, u64);
+
+// records the hits of the rule for all the cleartexts containing a matched fragment, the prefix
+// and suffix of the cleartext being appended to the rule
+pub fn record_matches(
+    hits: &mut HashMap<Vec<rules::Rule>, BTreeSet<u64>>,
+    rules: &[rules::Rule],
+    matches: &[CleartextInfo],
+) {
+    for (prefix, suffix, nth) in matches {
+        use rules::CommandRule::{Append, InsertString, Prefix};
+        use rules::Numerical::{Infinite, Val};
+        use rules::Rule::Command;
+        let mut currule = rules.to_vec();
+        if !prefix.is_empty() {
+            if prefix.len() == 1 {
+                currule.push(Command(Prefix(prefix[0])));
+            } else {
+                currule.push(Command(InsertString(Val(0), prefix.to_vec())));
+            }
+        }
+        if !suffix.is_empty() {
+            if suffix.len() == 1 {
+                currule.push(Command(Append(suffix[0])));
+            } else {
+                currule.push(Command(InsertString(Infinite, suffix.to_vec())));
+            }
+        }
+        record(hits, currule, *nth);
+    }
+}
+
+fn record(
+    hits: &mut HashMap<Vec<rules::Rule>, BTreeSet<u64>>,
+    currule: Vec<rules::Rule>,
+    nth: u64,
+) {
+    hits.entry(currule).or_default().insert(nth);
+}
+
+// largest position that can be displayed
+pub const MAX_POSITION: usize = 35;
+
+// finds the cleartexts that are the mutated word with a string of at most max_insertion
+// characters inserted inside it, using the fragment on either side of the insertion point; the
+// insertion is learned both relative to the start of the word, and relative to its end, as an
+// insertion in the reversed word
+fn record_insertions(
+    hits: &mut HashMap<Vec<rules::Rule>, BTreeSet<u64>>,
+    rules: &[rules::Rule],
+    mutated: &[u8],
     aclear: &CleartextMap,
-    cutoff: usize,
-) -> HashMap<Vec<rules::Rule>, Vec<u64>> {
-    let mut hits: HashMap<Vec<rules::Rule>, BTreeSet<u64>> = HashMap::new();
-    for word in wordlist.iter() {
-        match rules::mutate(word, &rules) {
-            None => (),
-            Some(mutated) => match aclear.get(&mutated.to_smallvec()) {
-                None => (),
-                Some(matches) => {
-                    for (prefix, suffix, nth) in matches {
-                        use rules::CommandRule::{Append, InsertString, Prefix};
-                        use rules::Numerical::{Infinite, Val};
-                        use rules::Rule::Command;
-                        let mut currule = rules.clone();
-                        if !prefix.is_empty() {
-                            if prefix.len() == 1 {
-                                currule.push(Command(Prefix(prefix[0])));
-                            } else {
-                                currule.push(Command(InsertString(Val(0), prefix.to_vec())));
-                            }
-                        }
-                        if !suffix.is_empty() {
-                            if suffix.len() == 1 {
-                                currule.push(Command(Append(suffix[0])));
-                            } else {
-                                currule.push(Command(InsertString(Infinite, suffix.to_vec())));
-                            }
-                        }
-                        hits.entry(currule)
-                            .and_modify(|hs| {
-                                hs.insert(*nth);
-                            })
-                            .or_insert_with(|| {
-                                let mut o = BTreeSet::new();
-                                o.insert(*nth);
-                                o
-                            });
-                    }
+    max_insertion: usize,
+) {
+    use rules::CommandRule::{InsertChar, InsertString, Reverse};
+    use rules::Numerical::Val;
+    use rules::Rule::Command;
+    let insert_at = |n: usize, s: &[u8]| {
+        Command(if s.len() == 1 {
+            InsertChar(Val(n as u8), s[0])
+        } else {
+            InsertString(Val(n as u8), s.to_vec())
+        })
+    };
+    let mut insert = |n: usize, s: &[u8], nth: u64| {
+        if s.is_empty() || s.len() > max_insertion {
+            return;
+        }
+        if n + s.len() - 1 <= MAX_POSITION {
+            let mut currule = rules.to_vec();
+            currule.push(insert_at(n, s));
+            record(hits, currule, nth);
+        }
+        let m = mutated.len() - n;
+        if m + s.len() - 1 <= MAX_POSITION {
+            let reversed: Vec<u8> = s.iter().rev().copied().collect();
+            let mut currule = rules.to_vec();
+            currule.push(Command(Reverse));
+            currule.push(insert_at(m, &reversed));
+            currule.push(Command(Reverse));
+            record(hits, currule, nth);
+        }
+    };
+    for n in 1..mutated.len() {
+        let (left, right) = mutated.split_at(n);
+        // the cleartext starts with the left part, and ends with the inserted string and the
+        // right part
+        if let Some(matches) = aclear.get(left) {
+            for (prefix, suffix, nth) in matches {
+                if prefix.is_empty() && suffix.ends_with(right) {
+                    insert(n, &suffix[..suffix.len() - right.len()], *nth);
                 }
-            },
-        };
+            }
+        }
+        // the other way around, for when the left part is too short to be a fragment
+        if let Some(matches) = aclear.get(right) {
+            for (prefix, suffix, nth) in matches {
+                if suffix.is_empty() && prefix.starts_with(left) {
+                    insert(n, &prefix[left.len()..], *nth);
+                }
+            }
+        }
     }
+}
+
+// applies the cutoff, and converts the hits to sorted vectors
+pub fn finish_hits(
+    mut hits: HashMap<Vec<rules::Rule>, BTreeSet<u64>>,
+    cutoff: usize,
+) -> HashMap<Vec<rules::Rule>, Vec<u64>> {
     hits.retain(|_, st| st.len() >= cutoff);
     let mut res: HashMap<Vec<rules::Rule>, Vec<u64>> = HashMap::new();
     for (k, st) in &hits {
@@ -63,6 +130,47 @@ pub fn worker_logic(
     res
 }
 
+// rules learned on top of the fragment matches
+#[derive(Default)]
+pub struct Extras {
+    pub max_insertion: usize,
+    pub edits: Option<EditIndex>,
+    pub casefold: Option<FoldedIndex>,
+}
+
+pub fn worker_logic(
+    rules: Vec<rules::Rule>,
+    wordlist: &[Vec<u8>],
+    aclear: &CleartextMap,
+    cutoff: usize,
+    extras: &Extras,
+) -> WorkerHits {
+    let mut hits: HashMap<Vec<rules::Rule>, BTreeSet<u64>> = HashMap::new();
+    let mut candidates = 0;
+    let program = compiled::compile(&rules);
+    let mut executor = compiled::Executor::new();
+    for word in wordlist.iter() {
+        let mutated = match executor.run(&program, word) {
+            None => continue,
+            Some(m) => m,
+        };
+        candidates += 1;
+        if let Some(matches) = aclear.get(mutated) {
+            record_matches(&mut hits, &rules, matches);
+        }
+        if extras.max_insertion > 0 {
+            record_insertions(&mut hits, &rules, mutated, aclear, extras.max_insertion);
+        }
+        if let Some(index) = &extras.edits {
+            index.record(&mut hits, &rules, mutated, aclear);
+        }
+        if let Some(index) = &extras.casefold {
+            index.record(&mut hits, &rules, mutated, aclear);
+        }
+    }
+    (finish_hits(hits, cutoff), candidates)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,7 +209,13 @@ mod tests {
 
         expected.clear();
         let cmd_truncate3 = Command(Truncate(Val(3)));
-        let res_truncate3 = worker_logic(vec![cmd_truncate3.clone()], &wordlist, &clears, 1);
+        let (res_truncate3, cnt_truncate3) = worker_logic(
+            vec![cmd_truncate3.clone()],
+            &wordlist,
+            &clears,
+            1,
+            &Extras::default(),
+        );
         expected.insert(
             vec![
                 cmd_truncate3.clone(),
@@ -114,11 +228,84 @@ mod tests {
             s2.clone(),
         );
         assert_eq!(res_truncate3, expected);
+        assert_eq!(cnt_truncate3, 4);
 
         expected.clear();
         let cmd_reverse = Command(Reverse);
-        let res_reverse = worker_logic(vec![cmd_reverse.clone()], &wordlist, &clears, 1);
+        let (res_reverse, _) = worker_logic(
+            vec![cmd_reverse.clone()],
+            &wordlist,
+            &clears,
+            1,
+            &Extras::default(),
+        );
         expected.insert(vec![cmd_reverse], s34.clone());
         assert_eq!(res_reverse, expected);
     }
+
+    #[test]
+    fn insertions() {
+        let wordlist = vec![conv("password"), conv("abc")];
+        let mut clears = HashMap::new();
+        cleartexts::process_line(&mut clears, 0, &conv("pass!word"), 3);
+        cleartexts::process_line(&mut clears, 1, &conv("p12assword"), 3);
+        cleartexts::process_line(&mut clears, 2, &conv("xpass!word"), 3);
+        cleartexts::process_line(&mut clears, 3, &conv("passwor1234d"), 3);
+        cleartexts::process_line(&mut clears, 4, &conv("a!bc"), 3);
+        cleartexts::process_line(&mut clears, 5, &conv("P!password"), 3);
+        let (res, _) = worker_logic(
+            vec![Command(Noop)],
+            &wordlist,
+            &clears,
+            1,
+            &Extras {
+                max_insertion: 3,
+                ..Default::default()
+            },
+        );
+        let mut expected = HashMap::new();
+        expected.insert(
+            vec![Command(Noop), Command(InsertChar(Val(4), b'!'))],
+            vec![0],
+        );
+        expected.insert(
+            vec![Command(Noop), Command(InsertString(Val(1), conv("12")))],
+            vec![1],
+        );
+        expected.insert(
+            vec![Command(Noop), Command(InsertString(Val(0), conv("P!")))],
+            vec![5],
+        );
+        // the same insertions, relative to the end of the word
+        let from_end = |m: u8, s: &str| {
+            let ins = if s.len() == 1 {
+                InsertChar(Val(m), s.as_bytes()[0])
+            } else {
+                InsertString(Val(m), conv(s))
+            };
+            vec![
+                Command(Noop),
+                Command(Reverse),
+                Command(ins),
+                Command(Reverse),
+            ]
+        };
+        expected.insert(from_end(4, "!"), vec![0]);
+        expected.insert(from_end(7, "21"), vec![1]);
+        assert_eq!(res, expected);
+        let clearlines = [
+            "pass!word",
+            "p12assword",
+            "",
+            "passwor1234d",
+            "",
+            "P!password",
+        ];
+        for (rule, hits) in &res {
+            assert_eq!(
+                rules::mutate(b"password", rule),
+                Some(conv(clearlines[hits[0] as usize]))
+            );
+        }
+    }
 }
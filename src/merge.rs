@@ -0,0 +1,404 @@
+use crate::rules::{self, CommandRule, Numerical, Rule};
+use crate::selection::HitMap;
+use std::collections::{BTreeSet, HashMap};
+
+// The selected rules often only differ by an affix character ($1, $2, $9 ...). They are merged
+// into JtR preprocessor classes ($[129]), and the classes are widened ($[0-9]) when the extra
+// candidates crack enough passwords.
+
+// a set of rules, where each alphanumeric affix character is replaced by a set of characters
+#[derive(Clone)]
+pub struct Pattern {
+    // representative rule
+    pub rules: Vec<Rule>,
+    slots: Vec<BTreeSet<u8>>,
+}
+
+// affix characters that can be replaced by a class, as (command, character) positions
+type Key = (Vec<Rule>, Vec<BTreeSet<u8>>);
+
+fn slot_positions(rules: &[Rule]) -> Vec<(usize, usize)> {
+    let mut o = Vec::new();
+    for (i, r) in rules.iter().enumerate() {
+        match r {
+            Rule::Command(CommandRule::Append(c)) | Rule::Command(CommandRule::Prefix(c))
+                if c.is_ascii_alphanumeric() =>
+            {
+                o.push((i, 0));
+            }
+            Rule::Command(CommandRule::InsertString(_, s)) => {
+                for (j, c) in s.iter().enumerate() {
+                    if c.is_ascii_alphanumeric() {
+                        o.push((i, j));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    o
+}
+
+fn set_char(rules: &mut [Rule], (i, j): (usize, usize), c: u8) {
+    match &mut rules[i] {
+        Rule::Command(CommandRule::Append(x)) | Rule::Command(CommandRule::Prefix(x)) => *x = c,
+        Rule::Command(CommandRule::InsertString(_, s)) => s[j] = c,
+        _ => unreachable!(),
+    }
+}
+
+fn show_set(set: &BTreeSet<u8>) -> String {
+    let chars: Vec<u8> = set.iter().cloned().collect();
+    if chars.len() == 1 {
+        return (chars[0] as char).to_string();
+    }
+    let mut o = String::from("[");
+    let mut i = 0;
+    while i < chars.len() {
+        let mut j = i;
+        while j + 1 < chars.len() && chars[j + 1] == chars[j] + 1 {
+            j += 1;
+        }
+        if j >= i + 2 {
+            o.push(chars[i] as char);
+            o.push('-');
+            o.push(chars[j] as char);
+        } else {
+            for c in &chars[i..=j] {
+                o.push(*c as char);
+            }
+        }
+        i = j + 1;
+    }
+    o.push(']');
+    o
+}
+
+// the full class containing all the characters
+fn widened(set: &BTreeSet<u8>) -> Option<BTreeSet<u8>> {
+    let classes: [fn(&u8) -> bool; 3] = [
+        u8::is_ascii_digit,
+        u8::is_ascii_lowercase,
+        u8::is_ascii_uppercase,
+    ];
+    classes
+        .iter()
+        .find(|f| set.iter().all(f))
+        .map(|f| (0..=255u8).filter(f).collect())
+}
+
+impl Pattern {
+    pub fn new(rules: &[Rule]) -> Self {
+        let slots = slot_positions(rules)
+            .into_iter()
+            .map(|(i, j)| {
+                let mut set = BTreeSet::new();
+                set.insert(match &rules[i] {
+                    Rule::Command(CommandRule::Append(c))
+                    | Rule::Command(CommandRule::Prefix(c)) => *c,
+                    Rule::Command(CommandRule::InsertString(_, s)) => s[j],
+                    _ => unreachable!(),
+                });
+                set
+            })
+            .collect();
+        Pattern {
+            rules: rules.to_vec(),
+            slots,
+        }
+    }
+
+    // the rule without its affix characters
+    fn shape(&self) -> Vec<Rule> {
+        let mut rules = self.rules.clone();
+        for pos in slot_positions(&self.rules) {
+            set_char(&mut rules, pos, 0);
+        }
+        rules
+    }
+
+    // identifies the patterns that only differ by the slot k
+    fn key(&self, k: usize) -> Key {
+        let mut slots = self.slots.clone();
+        slots.remove(k);
+        (self.shape(), slots)
+    }
+
+    fn size(&self) -> usize {
+        self.slots.iter().map(|s| s.len()).product()
+    }
+
+    // all the rules of the pattern
+    pub fn members(&self) -> Vec<Vec<Rule>> {
+        let positions = slot_positions(&self.rules);
+        let mut out = vec![self.rules.clone()];
+        for (pos, set) in positions.iter().zip(&self.slots) {
+            let mut next = Vec::with_capacity(out.len() * set.len());
+            for rules in &out {
+                for c in set {
+                    let mut r = rules.clone();
+                    set_char(&mut r, *pos, *c);
+                    next.push(r);
+                }
+            }
+            out = next;
+        }
+        out
+    }
+
+    // cracked passwords, and amount of candidates of one rule
+    fn coverage(&self, hits: &HitMap) -> (BTreeSet<u64>, u64) {
+        let mut cracked = BTreeSet::new();
+        let mut candidates = 0;
+        for m in self.members() {
+            if let Some(h) = hits.get(&m) {
+                cracked.extend(h.cracked.iter().cloned());
+                candidates = candidates.max(h.candidates);
+            }
+        }
+        (cracked, candidates)
+    }
+
+    pub fn cracked(&self, hits: &HitMap) -> BTreeSet<u64> {
+        self.coverage(hits).0
+    }
+
+    pub fn candidates(&self, hits: &HitMap) -> u64 {
+        self.coverage(hits).1 * self.size() as u64
+    }
+
+    pub fn show(&self, hashcat_mode: bool) -> Option<String> {
+        let positions = slot_positions(&self.rules);
+        let slot = |i: usize, j: usize| {
+            positions
+                .iter()
+                .position(|p| *p == (i, j))
+                .map(|k| show_set(&self.slots[k]))
+        };
+        let mut o = String::new();
+        for (i, r) in self.rules.iter().enumerate() {
+            let shown = match r {
+                Rule::Command(CommandRule::Append(c)) => {
+                    String::from("$") + &slot(i, 0).unwrap_or_else(|| rules::show_char(*c))
+                }
+                Rule::Command(CommandRule::Prefix(c)) => {
+                    String::from("^") + &slot(i, 0).unwrap_or_else(|| rules::show_char(*c))
+                }
+                Rule::Command(CommandRule::InsertString(n, s)) => {
+                    let parts: Vec<String> = s
+                        .iter()
+                        .enumerate()
+                        .map(|(j, c)| slot(i, j).unwrap_or_else(|| (*c as char).to_string()))
+                        .collect();
+                    if !hashcat_mode {
+                        // a separator that none of the characters can be
+                        let mut all: Vec<u8> = s.clone();
+                        all.extend(self.slots.iter().flatten());
+                        let sep = rules::show_string(&all).chars().next().unwrap();
+                        format!("A{}{}{}{}", rules::show_num(n), sep, parts.concat(), sep)
+                    } else {
+                        // the hashcat rendering escapes the literal characters
+                        let shown: Vec<String> = s
+                            .iter()
+                            .zip(parts)
+                            .map(|(c, p)| {
+                                if c.is_ascii_alphanumeric() {
+                                    p
+                                } else {
+                                    rules::show_char(*c)
+                                }
+                            })
+                            .collect();
+                        match n {
+                            Numerical::Val(0) => {
+                                shown.iter().rev().map(|p| format!("^{}", p)).collect()
+                            }
+                            Numerical::Infinite => {
+                                shown.iter().map(|p| format!("${}", p)).collect()
+                            }
+                            Numerical::Val(p) => shown
+                                .iter()
+                                .enumerate()
+                                .map(|(j, c)| {
+                                    format!(
+                                        "i{}{}",
+                                        rules::show_num(&Numerical::Val(p + j as u8)),
+                                        c
+                                    )
+                                })
+                                .collect(),
+                            _ => return None,
+                        }
+                    }
+                }
+                _ => rules::show_rule(r, hashcat_mode)?,
+            };
+            o += &shown;
+        }
+        Some(o)
+    }
+
+    // the commands must not be mistaken for preprocessor classes
+    fn mergeable(&self) -> bool {
+        !self.slots.is_empty()
+            && self.rules.iter().all(|r| {
+                [false, true].iter().all(|m| {
+                    rules::show_rule(r, *m).is_none_or(|s| !s.contains('[') && !s.contains(']'))
+                })
+            })
+    }
+}
+
+// merges the patterns that only differ by one slot, the first one of each group taking the place
+// of the others
+fn merge_slots(patterns: &mut Vec<Pattern>) -> bool {
+    let mut changed = false;
+    let max_slots = patterns.iter().map(|p| p.slots.len()).max().unwrap_or(0);
+    for k in 0..max_slots {
+        let mut first: HashMap<Key, usize> = HashMap::new();
+        let mut removed = vec![false; patterns.len()];
+        for i in 0..patterns.len() {
+            if k >= patterns[i].slots.len() || !patterns[i].mergeable() {
+                continue;
+            }
+            match first.get(&patterns[i].key(k)) {
+                Some(&f) => {
+                    let set = patterns[i].slots[k].clone();
+                    patterns[f].slots[k].extend(set);
+                    removed[i] = true;
+                    changed = true;
+                }
+                None => {
+                    first.insert(patterns[i].key(k), i);
+                }
+            }
+        }
+        let mut it = removed.iter();
+        patterns.retain(|_| !it.next().unwrap());
+    }
+    changed
+}
+
+// drops the patterns whose rules all belong to another pattern
+fn drop_subsumed(patterns: &mut Vec<Pattern>) -> bool {
+    let shapes: Vec<Vec<Rule>> = patterns.iter().map(|p| p.shape()).collect();
+    let subsumed = |q: usize, p: usize| {
+        p != q
+            && shapes[q] == shapes[p]
+            && patterns[q].slots.len() == patterns[p].slots.len()
+            && patterns[q]
+                .slots
+                .iter()
+                .zip(&patterns[p].slots)
+                .all(|(a, b)| a.is_subset(b))
+            && (patterns[q].size() < patterns[p].size() || q > p)
+    };
+    let removed: Vec<bool> = (0..patterns.len())
+        .map(|q| !patterns[q].slots.is_empty() && (0..patterns.len()).any(|p| subsumed(q, p)))
+        .collect();
+    let mut it = removed.iter();
+    patterns.retain(|_| !it.next().unwrap());
+    removed.contains(&true)
+}
+
+// widens the classes when the extra candidates crack at least min_efficiency passwords each
+fn widen_slots(patterns: &mut [Pattern], hits: &HitMap, min_efficiency: f64) -> bool {
+    let mut changed = false;
+    for p in patterns.iter_mut() {
+        for k in 0..p.slots.len() {
+            if p.slots[k].len() < 2 {
+                continue;
+            }
+            let class = match widened(&p.slots[k]) {
+                Some(c) if c != p.slots[k] => c,
+                _ => continue,
+            };
+            let mut wide = p.clone();
+            wide.slots[k] = class;
+            let (before, candidates) = p.coverage(hits);
+            let after = wide.cracked(hits);
+            let extra = (wide.size() - p.size()) as f64 * candidates as f64;
+            if candidates > 0 && (after.len() - before.len()) as f64 >= extra * min_efficiency {
+                *p = wide;
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+pub fn merge(selected: &[Vec<Rule>], hits: &HitMap, min_efficiency: f64) -> Vec<Pattern> {
+    let mut patterns: Vec<Pattern> = selected.iter().map(|r| Pattern::new(r)).collect();
+    loop {
+        let merged = merge_slots(&mut patterns);
+        let widened = widen_slots(&mut patterns, hits, min_efficiency);
+        let dropped = drop_subsumed(&mut patterns);
+        if !merged && !widened && !dropped {
+            break;
+        }
+    }
+    patterns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::CommandRule::*;
+    use crate::rules::Rule::Command;
+    use crate::selection::RuleHits;
+
+    fn hit(hits: &mut HitMap, rules: Vec<Rule>, cracked: &[u64]) {
+        hits.insert(
+            rules,
+            RuleHits {
+                candidates: 100,
+                cracked: cracked.to_vec(),
+            },
+        );
+    }
+
+    #[test]
+    fn classes() {
+        let mut hits = HashMap::new();
+        let suffix = |s: &str| {
+            vec![
+                Command(Reverse),
+                Command(InsertString(Numerical::Infinite, s.as_bytes().to_vec())),
+            ]
+        };
+        // every two digits suffix cracks something
+        for a in b'0'..=b'9' {
+            for b in b'0'..=b'9' {
+                let s = String::from_utf8(vec![a, b]).unwrap();
+                let id = (a - b'0') as u64 * 10 + (b - b'0') as u64;
+                hit(&mut hits, suffix(&s), &[id]);
+            }
+        }
+        hit(&mut hits, vec![Command(Append(b'1'))], &[200, 201, 202]);
+        hit(&mut hits, vec![Command(Append(b'2'))], &[203, 204]);
+        hit(&mut hits, vec![Command(Append(b'!'))], &[205, 206]);
+        let mut selected = vec![
+            vec![Command(Append(b'1'))],
+            vec![Command(Append(b'2'))],
+            vec![Command(Append(b'!'))],
+        ];
+        selected.extend(["12", "11", "21", "22", "13"].iter().map(|s| suffix(s)));
+
+        // no widening when nothing is worth the extra candidates
+        let patterns = merge(&selected, &hits, 1.0);
+        let shown: Vec<String> = patterns.iter().map(|p| p.show(false).unwrap()).collect();
+        assert_eq!(
+            shown,
+            vec!["$[12]", "$\\x21", "rAz\"[12][12]\"", "rAz\"13\""]
+        );
+        assert_eq!(patterns[0].cracked(&hits).len(), 5);
+
+        // a crack every 100 candidates is enough
+        let patterns = merge(&selected, &hits, 0.01);
+        let shown: Vec<String> = patterns.iter().map(|p| p.show(false).unwrap()).collect();
+        assert_eq!(shown, vec!["$[12]", "$\\x21", "rAz\"[0-9][0-9]\""]);
+        assert_eq!(patterns[2].cracked(&hits).len(), 100);
+        assert_eq!(patterns[2].show(true).unwrap(), "r$[0-9]$[0-9]");
+        assert_eq!(patterns[2].members().len(), 100);
+    }
+}
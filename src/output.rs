@@ -0,0 +1,145 @@
+use crate::rulefile;
+use crate::rules;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+// prints rules, switching to the hashcat logic in JtR mode when a rule requires it
+pub struct RulePrinter<W: Write = io::Stdout> {
+    out: W,
+    hashcat: bool,
+    hashcat_mode: bool,
+    details: bool,
+}
+
+impl RulePrinter {
+    pub fn new(hashcat: bool, details: bool) -> Self {
+        RulePrinter {
+            out: io::stdout(),
+            hashcat,
+            hashcat_mode: hashcat,
+            details,
+        }
+    }
+}
+
+impl<W: Write> RulePrinter<W> {
+    // stats are only displayed in details mode
+    pub fn print(&mut self, rules: &[rules::Rule], stats: &str) {
+        self.print_with(|hashcat_mode| rules::show_rules(rules, hashcat_mode), stats)
+    }
+
+    // prints a line rendered by show for the current logic, or the other one
+    pub fn print_with<F: Fn(bool) -> Option<String>>(&mut self, show: F, stats: &str) {
+        let rdesc = match show(self.hashcat_mode) {
+            Some(x) => x,
+            None => {
+                if self.hashcat {
+                    unreachable!(
+                        "should not happen : invalid rule to be displayed in hashcat mode: {:?}",
+                        show(false)
+                    )
+                }
+                self.hashcat_mode = !self.hashcat_mode;
+                if self.hashcat_mode {
+                    writeln!(self.out, "!! hashcat logic ON").unwrap();
+                } else {
+                    writeln!(self.out, "!! hashcat logic OFF").unwrap();
+                }
+                match show(self.hashcat_mode) {
+                    Some(r) => r,
+                    None => panic!("This rule is invalid with JtR & hashcat"),
+                }
+            }
+        };
+        if self.details {
+            writeln!(self.out, "{} // [{}]", rdesc, stats).unwrap();
+        } else {
+            writeln!(self.out, "{}", rdesc).unwrap();
+        }
+    }
+
+    pub fn finish(&mut self) {
+        if self.hashcat_mode && !self.hashcat {
+            writeln!(self.out, "!! hashcat logic OFF").unwrap();
+            self.hashcat_mode = false;
+        }
+    }
+}
+
+// finds the line of the base rule a selected rule was derived from
+pub struct Origins(HashMap<Vec<rules::Rule>, usize>);
+
+impl Origins {
+    pub fn new(file: &rulefile::RuleFile) -> Self {
+        let mut lines = HashMap::new();
+        for (line, rules) in &file.rules {
+            lines.entry(rules.clone()).or_insert(*line);
+        }
+        Origins(lines)
+    }
+
+    pub fn line(&self, rules: &[rules::Rule]) -> Option<usize> {
+        // at most a prefix and a suffix command are appended to the base rules
+        (0..=2.min(rules.len()))
+            .filter_map(|k| self.0.get(&rules[..rules.len() - k]))
+            .next()
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{CommandRule::*, Rule::Command};
+
+    #[test]
+    fn read_back() {
+        // JtR and hashcat only rules, so that the printer switches between the logics
+        let rules: Vec<Vec<rules::Rule>> = rules::genmutate()
+            .into_iter()
+            .filter(|rs| {
+                rules::show_rules(rs, false).is_some() || rules::show_rules(rs, true).is_some()
+            })
+            .collect();
+        assert!(rules
+            .iter()
+            .any(|rs| rules::show_rules(rs, false).is_none()));
+        for details in &[false, true] {
+            let mut printer = RulePrinter {
+                out: Vec::new(),
+                hashcat: false,
+                hashcat_mode: false,
+                details: *details,
+            };
+            for rs in &rules {
+                printer.print(rs, "1 - 1");
+            }
+            printer.finish();
+            let path = std::env::temp_dir().join(format!("rf-printed-{}", std::process::id()));
+            std::fs::write(&path, &printer.out).unwrap();
+            let file = rulefile::read_rules(&path, false, true).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(file.errors, Vec::<String>::new());
+            let read: Vec<Vec<rules::Rule>> = file.rules.into_iter().map(|(_, rs)| rs).collect();
+            assert_eq!(read, rules);
+        }
+    }
+
+    #[test]
+    fn origins() {
+        let file = rulefile::RuleFile {
+            rules: vec![
+                (3, vec![Command(Reverse)]),
+                (4, vec![Command(Reverse), Command(Append(b'1'))]),
+            ],
+            errors: Vec::new(),
+        };
+        let origins = Origins::new(&file);
+        let derived = |r: &[u8]| origins.line(&rulefile::parse_rule(r, true).unwrap());
+        assert_eq!(derived(b"r$1"), Some(4));
+        assert_eq!(derived(b"r$2"), Some(3));
+        assert_eq!(derived(b"r^2$1$2"), None);
+        assert_eq!(derived(b"r^2$2"), Some(3));
+        assert_eq!(derived(b"$2"), None);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::rules::{in_class, CharClass, CharSelector};
+
+// The password policy of the target system: the cleartexts it would not have accepted are not
+// used for training. The rejection commands enforcing it on the candidates are in rejects.rs, as
+// this module is shared with the dumper.
+
+pub const CLASSES: [(u8, CharSelector); 4] = [
+    (b'l', CharSelector::OneOf(CharClass::CCLower)),
+    (b'u', CharSelector::OneOf(CharClass::CCUpper)),
+    (b'd', CharSelector::OneOf(CharClass::CCDigits)),
+    (b's', CharSelector::NoneOf(CharClass::CCAlphaNum)),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Policy {
+    pub min_len: usize,
+    pub max_len: Option<usize>,
+    // minimum amount of character classes among lowercase, uppercase, digits and others
+    pub classes: usize,
+    // indices in CLASSES of the classes that are always required
+    pub required: Vec<usize>,
+}
+
+impl std::str::FromStr for Policy {
+    type Err = String;
+    // minlen=8,maxlen=64,classes=3,require=ds
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = Policy {
+            min_len: 0,
+            max_len: None,
+            classes: 0,
+            required: Vec::new(),
+        };
+        for item in s.split(',') {
+            let (key, value) = item
+                .split_once('=')
+                .ok_or_else(|| format!("invalid policy item {}, expected key=value", item))?;
+            let number = || {
+                value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n < 254)
+                    .ok_or_else(|| format!("invalid {}: {}", key, value))
+            };
+            match key {
+                "minlen" => policy.min_len = number()?,
+                "maxlen" => policy.max_len = Some(number()?),
+                "classes" => policy.classes = number()?.min(CLASSES.len()),
+                "require" => {
+                    for c in value.bytes() {
+                        let i = CLASSES.iter().position(|(k, _)| *k == c).ok_or_else(|| {
+                            format!(
+                                "unknown class {}, accepted classes are l, u, d and s",
+                                c as char
+                            )
+                        })?;
+                        if !policy.required.contains(&i) {
+                            policy.required.push(i);
+                        }
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                    "unknown policy key {}, accepted keys are minlen, maxlen, classes and require",
+                    key
+                ))
+                }
+            }
+        }
+        Ok(policy)
+    }
+}
+
+impl Policy {
+    pub fn accepts(&self, word: &[u8]) -> bool {
+        let present: Vec<bool> = CLASSES
+            .iter()
+            .map(|(_, cs)| word.iter().any(|c| in_class(*c, cs)))
+            .collect();
+        word.len() >= self.min_len
+            && self.max_len.is_none_or(|m| word.len() <= m)
+            && present.iter().filter(|p| **p).count() >= self.classes
+            && self.required.iter().all(|i| present[*i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy() {
+        let policy: Policy = "minlen=8,classes=3,require=d".parse().unwrap();
+        assert!(policy.accepts(b"Password1"));
+        assert!(policy.accepts(b"password1!"));
+        assert!(!policy.accepts(b"Passw0r"));
+        assert!(!policy.accepts(b"password1"));
+        assert!(!policy.accepts(b"Password!"));
+        assert!("minlen=8,digits=1".parse::<Policy>().is_err());
+        assert!("require=x".parse::<Policy>().is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use crate::matcher::MAX_POSITION;
+use crate::policy::{Policy, CLASSES};
+use crate::rules::{Numerical, RejectRule, Rule};
+
+// The JtR rejection commands appended to the rules, so that no candidate is wasted on passwords
+// the target system would not have accepted.
+
+impl Policy {
+    // the rejection commands enforcing the policy on the final candidate; a minimum amount of
+    // classes can only be enforced when all of them are required, and lengths up to 35
+    pub fn rejects(&self) -> Vec<Rule> {
+        let mut out = Vec::new();
+        if self.min_len > 1 && self.min_len <= MAX_POSITION + 1 {
+            out.push(Rule::Reject(RejectRule::UnlessWordLengthMoreThan(
+                Numerical::Val(self.min_len as u8 - 1),
+            )));
+        }
+        if let Some(m) = self.max_len.filter(|m| *m < MAX_POSITION) {
+            out.push(Rule::Reject(RejectRule::UnlessWordLengthLessThan(
+                Numerical::Val(m as u8 + 1),
+            )));
+        }
+        for (i, (_, cs)) in CLASSES.iter().enumerate() {
+            if self.classes == CLASSES.len() || self.required.contains(&i) {
+                out.push(Rule::Reject(RejectRule::UnlessContain(cs.clone())));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{mutate, show_rules};
+
+    #[test]
+    fn policy_rejects() {
+        let policy: Policy = "minlen=8,classes=3,require=d".parse().unwrap();
+        let rejects = policy.rejects();
+        assert_eq!(show_rules(&rejects, false).unwrap(), ">7/?d");
+        assert!(mutate(b"password1", &rejects).is_some());
+        assert!(mutate(b"password", &rejects).is_none());
+        assert!(mutate(b"passwo1", &rejects).is_none());
+        let policy: Policy = "minlen=36,maxlen=64".parse().unwrap();
+        assert_eq!(show_rules(&policy.rejects(), false).unwrap(), ">Z");
+
+        let policy: Policy = "maxlen=10,classes=4".parse().unwrap();
+        assert_eq!(
+            show_rules(&policy.rejects(), false).unwrap(),
+            "<B/?l/?u/?d/?X"
+        );
+    }
+}
@@ -0,0 +1,489 @@
+use crate::rules::{CharClass, CharSelector, CommandRule, Numerical, RejectRule, Rule, UserVar};
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+// Parsing of JtR and hashcat rule files, so that users can supply their own rules.
+
+// maximum amount of rules a single line can expand to
+const MAX_EXPANSION: usize = 1_000_000;
+
+fn hex_digit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+// reads an escaped character (after the backslash): \xHH or any character
+fn unescape_at(line: &[u8], pos: &mut usize) -> Result<u8, String> {
+    let c = *line.get(*pos).ok_or("trailing backslash")?;
+    *pos += 1;
+    if c == b'x' {
+        if let (Some(h), Some(l)) = (
+            line.get(*pos).cloned().and_then(hex_digit),
+            line.get(*pos + 1).cloned().and_then(hex_digit),
+        ) {
+            *pos += 2;
+            return Ok(h * 16 + l);
+        }
+    }
+    Ok(c)
+}
+
+// replaces the \xHH sequences with the corresponding bytes, for rules that are not preprocessed
+pub fn unescape_hex(line: &[u8]) -> Vec<u8> {
+    let mut o = Vec::new();
+    let mut pos = 0;
+    while pos < line.len() {
+        let c = line[pos];
+        pos += 1;
+        if c == b'\\' && line.get(pos) == Some(&b'x') {
+            let mut p = pos;
+            if let Ok(x) = unescape_at(line, &mut p) {
+                if p == pos + 3 {
+                    o.push(x);
+                    pos = p;
+                    continue;
+                }
+            }
+        }
+        o.push(c);
+    }
+    o
+}
+
+// JtR preprocessor: [abc] and [a-z] ranges get expanded, and \ escapes the next character
+pub fn expand(line: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    // possible characters at each position
+    let mut sets: Vec<Vec<u8>> = Vec::new();
+    let mut pos = 0;
+    while pos < line.len() {
+        let c = line[pos];
+        pos += 1;
+        match c {
+            b'\\' => sets.push(vec![unescape_at(line, &mut pos)?]),
+            b'[' => {
+                let mut set = Vec::new();
+                loop {
+                    let c = match line.get(pos) {
+                        None => return Err(String::from("unterminated range")),
+                        Some(b']') => {
+                            pos += 1;
+                            break;
+                        }
+                        Some(b'\\') => {
+                            pos += 1;
+                            unescape_at(line, &mut pos)?
+                        }
+                        Some(c) => {
+                            pos += 1;
+                            *c
+                        }
+                    };
+                    // a-z, a dash at the end of the range being a regular character
+                    if line.get(pos) == Some(&b'-') && line.get(pos + 1).is_some_and(|&e| e != b']')
+                    {
+                        let mut end = line[pos + 1];
+                        pos += 2;
+                        if end == b'\\' {
+                            end = unescape_at(line, &mut pos)?;
+                        }
+                        if end < c {
+                            return Err(format!("invalid range {}-{}", c as char, end as char));
+                        }
+                        set.extend(c..=end);
+                    } else {
+                        set.push(c);
+                    }
+                }
+                if set.is_empty() {
+                    return Err(String::from("empty range"));
+                }
+                sets.push(set);
+            }
+            _ => sets.push(vec![c]),
+        }
+    }
+    let total = sets
+        .iter()
+        .try_fold(1usize, |acc, s| acc.checked_mul(s.len()))
+        .filter(|t| *t <= MAX_EXPANSION)
+        .ok_or_else(|| format!("expands to more than {} rules", MAX_EXPANSION))?;
+    let mut out: Vec<Vec<u8>> = Vec::with_capacity(total);
+    out.push(Vec::new());
+    for set in sets {
+        if set.len() == 1 {
+            for o in out.iter_mut() {
+                o.push(set[0]);
+            }
+            continue;
+        }
+        let mut next = Vec::with_capacity(out.len() * set.len());
+        for o in &out {
+            for c in &set {
+                let mut n = o.clone();
+                n.push(*c);
+                next.push(n);
+            }
+        }
+        out = next;
+    }
+    Ok(out)
+}
+
+struct Cursor<'a> {
+    s: &'a [u8],
+    pos: usize,
+    // hashcat meaning of the commands both tools use differently
+    hashcat: bool,
+    // JtR extensions: numerical constants, classes, flags and JtR only commands
+    jtr: bool,
+}
+
+impl<'a> Cursor<'a> {
+    fn next(&mut self) -> Result<u8, String> {
+        let c = *self.s.get(self.pos).ok_or("missing argument")?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn num(&mut self) -> Result<Numerical, String> {
+        use Numerical::*;
+        let c = self.next()?;
+        Ok(match c {
+            b'0'..=b'9' => Val(c - b'0'),
+            b'A'..=b'Z' => Val(c - b'A' + 10),
+            _ if !self.jtr => return Err(format!("invalid position {}", c as char)),
+            b'#' => MinLen,
+            b'@' => MinLenMinus1,
+            b'$' => MinLenPlus1,
+            b'*' => MaxLen,
+            b'-' => MaxLenMinus1,
+            b'+' => MaxLenPlus1,
+            b'l' => WordLen,
+            b'm' => WordLastCharPos,
+            b'p' => LastFound,
+            b'z' => Infinite,
+            _ => SavedLen(uservar(c)?),
+        })
+    }
+
+    // a character, or a JtR character class
+    fn class(&mut self) -> Result<CharSelector, String> {
+        use CharClass::*;
+        let c = self.next()?;
+        if !self.jtr || c != b'?' {
+            return Ok(CharSelector::OneOf(CCSingle(c)));
+        }
+        let k = self.next()?;
+        let cl = match k.to_ascii_lowercase() {
+            b'?' => return Ok(CharSelector::OneOf(CCSingle(b'?'))),
+            b'v' => CCVowels,
+            b'c' => CCConsonants,
+            b'w' => CCWhitespace,
+            b'p' => CCPunctuation,
+            b's' => CCSymbols,
+            b'l' => CCLower,
+            b'u' => CCUpper,
+            b'd' => CCDigits,
+            b'a' => CCLetters,
+            b'x' => CCAlphaNum,
+            b'o' => CCControl,
+            b'z' => CCAll,
+            b'b' => CCBit8,
+            _ => return Err(format!("unknown character class ?{}", k as char)),
+        };
+        Ok(if k.is_ascii_uppercase() {
+            CharSelector::NoneOf(cl)
+        } else {
+            CharSelector::OneOf(cl)
+        })
+    }
+
+    // a string delimited by its first character
+    fn string(&mut self) -> Result<Vec<u8>, String> {
+        let sep = self.next()?;
+        let mut o = Vec::new();
+        loop {
+            match self.next() {
+                Ok(c) if c == sep => return Ok(o),
+                Ok(c) => o.push(c),
+                Err(_) => return Err(String::from("unterminated string")),
+            }
+        }
+    }
+}
+
+fn uservar(c: u8) -> Result<UserVar, String> {
+    use UserVar::*;
+    Ok(match c {
+        b'a' => UVA,
+        b'b' => UVB,
+        b'c' => UVC,
+        b'd' => UVD,
+        b'e' => UVE,
+        b'f' => UVF,
+        b'g' => UVG,
+        b'h' => UVH,
+        b'i' => UVI,
+        b'j' => UVJ,
+        b'k' => UVK,
+        _ => return Err(format!("invalid position {}", c as char)),
+    })
+}
+
+fn parse_reject(c: u8, cur: &mut Cursor) -> Result<Option<RejectRule>, String> {
+    use RejectRule::*;
+    let r = match c {
+        b'<' => UnlessWordLengthLessThan(cur.num()?),
+        b'>' => UnlessWordLengthMoreThan(cur.num()?),
+        b'_' => UnlessWordLengthIs(cur.num()?),
+        b'!' => IfContain(cur.class()?),
+        b'/' => UnlessContain(cur.class()?),
+        b'=' => UnlessCharAt(cur.num()?, cur.class()?),
+        b'(' => UnlessFirstChar(cur.class()?),
+        b')' => UnlessLastChar(cur.class()?),
+        b'%' => UnlessAtLeastNTimes(cur.num()?, cur.class()?),
+        b'Q' => RejectTheWordUnlessDifferent,
+        _ if !cur.jtr => return Ok(None),
+        b'a' => UnlessValidAfterAdding(cur.num()?),
+        b'b' => UnlessValidAfterRemoving(cur.num()?),
+        b'U' => UnlessValidUtf8,
+        b'-' if !cur.hashcat => match cur.next()? {
+            b':' => Noop,
+            b'c' => UnlessCaseSensitive,
+            b'8' => Unless8bits,
+            b's' => UnlessSplit,
+            b'p' => UnlessWordPairs,
+            b'u' => UnlessUtf8,
+            b'U' => IfUtf8,
+            b'>' => UnlessSupportedLengthOrLonger(cur.num()?),
+            b'<' => UnlessSupportedLengthOrShorted(cur.num()?),
+            f => return Err(format!("unknown flag -{}", f as char)),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(r))
+}
+
+fn parse_command(c: u8, cur: &mut Cursor) -> Result<CommandRule, String> {
+    use CommandRule::*;
+    Ok(match c {
+        b':' => Noop,
+        b'l' => ToLower,
+        b'u' => ToUpper,
+        b'c' => Capitalize,
+        b'C' => InvertCapitalize,
+        b't' => ToggleAll,
+        b'T' => ToggleCase(cur.num()?),
+        b'r' => Reverse,
+        b'd' => Duplicate,
+        b'f' => Reflect,
+        b'{' => RotLeft,
+        b'}' => RotRight,
+        b'$' => Append(cur.next()?),
+        b'^' => Prefix(cur.next()?),
+        b'[' => DeleteFirst,
+        b']' => DeleteLast,
+        b'D' => DeleteAt(cur.num()?),
+        b'x' => Extract(cur.num()?, cur.num()?),
+        b'O' => OmitRange(cur.num()?, cur.num()?),
+        b'i' => InsertChar(cur.num()?, cur.next()?),
+        b'o' => Overstrike(cur.num()?, cur.next()?),
+        b'\'' => Truncate(cur.num()?),
+        b's' => ReplaceAll(cur.class()?, cur.next()?),
+        b'@' => PurgeAll(cur.class()?),
+        b'z' => DupeFirstChar(cur.num()?),
+        b'Z' => DupeLastChar(cur.num()?),
+        b'q' => DupeAllChar,
+        b'X' => ExtractInsert(cur.num()?, cur.num()?, cur.num()?),
+        b'4' => AppendMemory,
+        b'6' => PrependMemory,
+        b'M' => Memorize,
+        // hashcat only
+        b'p' if cur.hashcat => DupWordNTimes(cur.num()?),
+        b'L' if cur.hashcat => BitshiftLeft(cur.num()?),
+        b'R' if cur.hashcat => BitshiftRight(cur.num()?),
+        b'k' if cur.hashcat => SwapFirstTwo,
+        b'K' if cur.hashcat => SwapLastTwo,
+        b'*' if cur.hashcat => Swap(cur.num()?, cur.num()?),
+        b'+' if cur.hashcat => Increment(cur.num()?),
+        b'-' if cur.hashcat => Decrement(cur.num()?),
+        b'.' if cur.hashcat => ReplaceWithNext(cur.num()?),
+        b',' if cur.hashcat => ReplaceWithPrior(cur.num()?),
+        b'y' if cur.hashcat => DupFirstString(cur.num()?),
+        b'Y' if cur.hashcat => DupLastString(cur.num()?),
+        // john only
+        b'E' if !cur.hashcat => TitleCase(cur.class()?),
+        b'L' => ShiftAllKeyboardLeft,
+        b'R' => ShiftAllKeyboardRight,
+        b'S' if cur.jtr => ShiftAll,
+        b'V' if cur.jtr => LowerVowelsUpperConsonants,
+        b'W' if cur.jtr => ToggleShift(cur.num()?),
+        b'p' => Pluralize,
+        b'P' if cur.jtr => PastTense,
+        b'I' if cur.jtr => Genitive,
+        b'A' if cur.jtr => InsertString(cur.num()?, cur.string()?),
+        b'v' if cur.jtr => MemoryAssign(uservar(cur.next()?)?, cur.num()?, cur.num()?),
+        _ => return Err(format!("unsupported command {}", c as char)),
+    })
+}
+
+// parses a rule, after preprocessing
+pub fn parse_rule(rule: &[u8], hashcat: bool) -> Result<Vec<Rule>, String> {
+    parse_rule_with(rule, hashcat, !hashcat)
+}
+
+// the rules of a JtR "hashcat logic" section give the hashcat meaning to the commands, but keep
+// the JtR extensions
+fn parse_rule_with(rule: &[u8], hashcat: bool, jtr: bool) -> Result<Vec<Rule>, String> {
+    let mut cur = Cursor {
+        s: rule,
+        pos: 0,
+        hashcat,
+        jtr,
+    };
+    let mut out = Vec::new();
+    while cur.pos < rule.len() {
+        let c = cur.next()?;
+        if c == b' ' || c == b'\t' {
+            continue;
+        }
+        out.push(match parse_reject(c, &mut cur)? {
+            Some(r) => Rule::Reject(r),
+            None => Rule::Command(parse_command(c, &mut cur)?),
+        });
+    }
+    if out.is_empty() {
+        return Err(String::from("empty rule"));
+    }
+    Ok(out)
+}
+
+// parses a line of a rule file, that can expand to several rules when preprocessed
+pub fn parse_line(line: &[u8], hashcat: bool, preprocess: bool) -> Result<Vec<Vec<Rule>>, String> {
+    let raw = if preprocess {
+        expand(line)?
+    } else {
+        vec![unescape_hex(line)]
+    };
+    raw.iter().map(|r| parse_rule(r, hashcat)).collect()
+}
+
+pub struct RuleFile {
+    // rules, and the line they come from
+    pub rules: Vec<(usize, Vec<Rule>)>,
+    // lines that could not be parsed
+    pub errors: Vec<String>,
+}
+
+// reads a rule file, skipping comments, empty lines, JtR section headers and directives; the
+// rules between the "!! hashcat logic ON" and "!! hashcat logic OFF" directives, as printed by
+// RulePrinter, are read with the hashcat syntax and without preprocessing
+pub fn read_rules(path: &Path, hashcat: bool, preprocess: bool) -> Result<RuleFile, String> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut out = RuleFile {
+        rules: Vec::new(),
+        errors: Vec::new(),
+    };
+    let mut hashcat_logic = false;
+    for (i, rawline) in io::BufReader::new(file).split(b'\n').enumerate() {
+        let mut line = rawline.map_err(|e| format!("{}: {}", path.display(), e))?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        // the statistics printed by --details
+        if line.ends_with(b"]") {
+            if let Some(p) = line.windows(5).rposition(|w| w == b" // [") {
+                line.truncate(p);
+            }
+        }
+        if line.starts_with(b"!! hashcat logic ") {
+            hashcat_logic = &line[17..] == b"ON";
+            continue;
+        }
+        if line.iter().all(|c| c.is_ascii_whitespace())
+            || line.starts_with(b"#")
+            || line.starts_with(b"!!")
+            || line.starts_with(b"[List.")
+        {
+            continue;
+        }
+        let parsed = if hashcat_logic {
+            parse_rule_with(&unescape_hex(&line), true, true).map(|r| vec![r])
+        } else {
+            parse_line(&line, hashcat, preprocess)
+        };
+        match parsed {
+            Ok(rules) => out.rules.extend(rules.into_iter().map(|r| (i + 1, r))),
+            Err(e) => out.errors.push(format!(
+                "{}:{}: {} ({})",
+                path.display(),
+                i + 1,
+                e,
+                String::from_utf8_lossy(&line)
+            )),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{self, CommandRule::*, Numerical::*, Rule::Command};
+
+    #[test]
+    fn preprocessor() {
+        let res = expand(b"s[ab][0-2]\\[").unwrap();
+        let expected: Vec<Vec<u8>> = ["sa0[", "sa1[", "sa2[", "sb0[", "sb1[", "sb2["]
+            .iter()
+            .map(|x| x.as_bytes().to_vec())
+            .collect();
+        assert_eq!(res, expected);
+        assert_eq!(expand(b"$[\\x41-C-]").unwrap().len(), 4);
+        assert!(expand(b"$[a-z").is_err());
+        assert!(expand(b"[a-z][a-z][a-z][a-z][a-z]").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        for hashcat in &[false, true] {
+            for rs in rules::genmutate() {
+                let supported = if *hashcat {
+                    rs.iter().all(rules::hashcat_rule)
+                } else {
+                    rs.iter().all(rules::john_rule)
+                };
+                if let Some(shown) = rules::show_rules(&rs, *hashcat).filter(|_| supported) {
+                    let parsed = parse_line(shown.as_bytes(), *hashcat, !*hashcat).unwrap();
+                    assert_eq!(parsed, vec![rs.clone()], "{}", shown);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn syntax() {
+        assert_eq!(
+            parse_rule(b"c $1 $2 -5 p2", true).unwrap(),
+            vec![
+                Command(Capitalize),
+                Command(Append(b'1')),
+                Command(Append(b'2')),
+                Command(Decrement(Val(5))),
+                Command(DupWordNTimes(Val(2))),
+            ]
+        );
+        assert_eq!(
+            parse_rule(b"-c>4pA0\"ab\"s?D!", false).unwrap(),
+            vec![
+                Rule::Reject(RejectRule::UnlessCaseSensitive),
+                Rule::Reject(RejectRule::UnlessWordLengthMoreThan(Val(4))),
+                Command(Pluralize),
+                Command(InsertString(Val(0), b"ab".to_vec())),
+                Command(ReplaceAll(CharSelector::NoneOf(CharClass::CCDigits), b'!')),
+            ]
+        );
+        assert!(parse_rule(b"$", true).is_err());
+        assert!(parse_rule(b"S", true).is_err());
+    }
+}
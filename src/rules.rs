@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+#[allow(dead_code)]
 #[derive(Hash, Eq, PartialEq, Debug, Clone, PartialOrd)]
 enum ToolSupport {
     JtR,
@@ -41,11 +42,11 @@ fn rules_init_conv(ssrc: &str, sdst: &str) -> [u8; 256] {
 }
 
 pub struct Converts {
-    cshift: [u8; 256],
-    cinvert: [u8; 256],
-    cleft: [u8; 256],
-    cright: [u8; 256],
-    cvowels: [u8; 256],
+    pub cshift: [u8; 256],
+    pub cinvert: [u8; 256],
+    pub cleft: [u8; 256],
+    pub cright: [u8; 256],
+    pub cvowels: [u8; 256],
 }
 
 pub fn make_converts() -> Converts {
@@ -58,7 +59,7 @@ pub fn make_converts() -> Converts {
     }
 }
 
-#[allow(dead_code)]
+#[allow(dead_code, clippy::upper_case_acronyms)]
 #[derive(Hash, Eq, PartialEq, Debug, Clone, PartialOrd)]
 pub enum UserVar {
     UVA,
@@ -403,13 +404,11 @@ fn check_class(c: u8, cl: &CharClass) -> bool {
         CCWhitespace => CHARS_WHITESPACE.contains(&c),
         CCPunctuation => CHARS_PUNCTUATION.contains(&c),
         CCSymbols => CHARS_SPECIALS.contains(&c),
-        CCLower => (b'a'..=b'z').contains(&c),
-        CCUpper => (b'A'..=b'Z').contains(&c),
-        CCDigits => (b'0'..=b'9').contains(&c),
-        CCLetters => (b'a'..=b'z').contains(&c) || (b'A'..=b'Z').contains(&c),
-        CCAlphaNum => {
-            (b'a'..=b'z').contains(&c) || (b'A'..=b'Z').contains(&c) || (b'0'..=b'9').contains(&c)
-        }
+        CCLower => c.is_ascii_lowercase(),
+        CCUpper => c.is_ascii_uppercase(),
+        CCDigits => c.is_ascii_digit(),
+        CCLetters => c.is_ascii_alphabetic(),
+        CCAlphaNum => c.is_ascii_alphanumeric(),
         CCControl => CHARS_CONTROL_ASCII.contains(&c),
         CCAll => true,
         CCBit8 => c > 127,
@@ -417,7 +416,7 @@ fn check_class(c: u8, cl: &CharClass) -> bool {
     }
 }
 
-fn in_class(c: u8, cl: &CharSelector) -> bool {
+pub fn in_class(c: u8, cl: &CharSelector) -> bool {
     use CharSelector::*;
     match cl {
         OneOf(cl_) => check_class(c, cl_),
@@ -425,22 +424,23 @@ fn in_class(c: u8, cl: &CharSelector) -> bool {
     }
 }
 
+// JtR rejection commands, word being the current state of the candidate
 fn must_reject(rj: &RejectRule, word: &[u8], env: &RuleEnv) -> bool {
     use RejectRule::*;
     match rj {
-        UnlessWordLengthLessThan(n) => word.len() as u8 <= eval_length(n, env),
-        UnlessWordLengthMoreThan(n) => word.len() as u8 >= eval_length(n, env),
-        UnlessWordLengthIs(n) => word.len() as u8 == eval_length(n, env),
-        IfContain(cl) => word.iter().all(|c| in_class(*c, cl)),
-        UnlessContain(cl) => !word.iter().all(|c| in_class(*c, cl)),
+        UnlessWordLengthLessThan(n) => word.len() >= eval_length(n, env) as usize,
+        UnlessWordLengthMoreThan(n) => word.len() <= eval_length(n, env) as usize,
+        UnlessWordLengthIs(n) => word.len() != eval_length(n, env) as usize,
+        IfContain(cl) => word.iter().any(|c| in_class(*c, cl)),
+        UnlessContain(cl) => !word.iter().any(|c| in_class(*c, cl)),
         UnlessCharAt(n, cl) => word
             .get(eval_length(n, env) as usize)
-            .map(|c| in_class(*c, cl))
+            .map(|c| !in_class(*c, cl))
             .unwrap_or(true),
-        UnlessFirstChar(cl) => word.first().map(|c| in_class(*c, cl)).unwrap_or(true),
-        UnlessLastChar(cl) => word.last().map(|c| in_class(*c, cl)).unwrap_or(true),
+        UnlessFirstChar(cl) => word.first().map(|c| !in_class(*c, cl)).unwrap_or(true),
+        UnlessLastChar(cl) => word.last().map(|c| !in_class(*c, cl)).unwrap_or(true),
         UnlessAtLeastNTimes(n, cl) => {
-            word.iter().filter(|c| in_class(**c, cl)).count() >= eval_length(n, env) as usize
+            word.iter().filter(|c| in_class(**c, cl)).count() < eval_length(n, env) as usize
         }
         _ => false,
     }
@@ -453,7 +453,7 @@ fn toggle(tbl: [u8; 256], c: &mut u8) {
     }
 }
 
-fn run_conv(cur: &mut Vec<u8>, tbl: [u8; 256]) {
+fn run_conv(cur: &mut [u8], tbl: [u8; 256]) {
     for c in cur.iter_mut() {
         let x = tbl[*c as usize];
         if x != 0 {
@@ -528,7 +528,8 @@ pub fn mutate(word: &[u8], rules: &[Rule]) -> Option<Vec<u8>> {
                     Append(c) => cur.push(*c),
                     Prefix(c) => cur.insert(0, *c),
                     InsertString(n, s) => {
-                        let pos = eval_length(n, &env) as usize;
+                        // past the end of the word, the string is appended
+                        let pos = (eval_length(n, &env) as usize).min(cur.len());
                         let after = cur.split_off(pos);
                         let middle = s.clone();
                         cur.extend(middle);
@@ -911,9 +912,16 @@ pub fn show_command(cmd: &CommandRule, hashcat_mode: bool) -> Option<String> {
                             o.push_str(show_char(*c).as_str());
                         }
                     }
+                    Numerical::Val(p) => {
+                        for (i, c) in s.iter().enumerate() {
+                            o.push('i');
+                            o.push_str(show_num(&Numerical::Val(p + i as u8)).as_str());
+                            o.push_str(show_char(*c).as_str());
+                        }
+                    }
                     _ => {
-                        // this should not happen as InsertString rules are not generated, and can
-                        // only be caused by the worker_logic command.
+                        // this should not happen as InsertString rules are only generated with
+                        // fixed positions, by the worker_logic command.
                         println!("unhandled command: {:?}", cmd);
                         o = String::from("UNHANDLED");
                     }
@@ -990,6 +998,7 @@ fn support_commandrule(r: &CommandRule) -> ToolSupport {
     }
 }
 
+#[allow(dead_code)]
 pub fn john_rule(r: &Rule) -> bool {
     match r {
         Rule::Reject(_) => true,
@@ -1008,7 +1017,7 @@ pub fn show_num(n: &Numerical) -> String {
     use Numerical::*;
     match n {
         Val(n) => {
-            if *n > 10 {
+            if *n >= 10 {
                 ((*n - 10 + b'A') as char).to_string()
             } else {
                 ((*n + b'0') as char).to_string()
@@ -1029,7 +1038,7 @@ pub fn show_num(n: &Numerical) -> String {
 }
 
 pub fn show_char(c: u8) -> String {
-    if (b'0'..=b'9').contains(&c) || (b'A'..=b'Z').contains(&c) || (b'a'..=b'z').contains(&c) {
+    if c.is_ascii_alphanumeric() {
         let mut o = String::new();
         o.push(c as char);
         return o;
@@ -1087,7 +1096,7 @@ pub fn show_cc(cs: &CharClass) -> String {
         CCLower => String::from("?l"),
         CCUpper => String::from("?u"),
         CCDigits => String::from("?d"),
-        CCLetters => String::from("?l"),
+        CCLetters => String::from("?a"),
         CCAlphaNum => String::from("?x"),
         CCControl => String::from("?o"),
         CCAll => String::from("?z"),
@@ -1097,16 +1106,13 @@ pub fn show_cc(cs: &CharClass) -> String {
 }
 
 pub fn show_cs(cs: &CharSelector) -> String {
-    let mut o = String::new();
-
-    let cc = match cs {
-        CharSelector::OneOf(x) => x,
-        CharSelector::NoneOf(x) => {
-            o += "!";
-            x
-        }
-    };
-    o + show_cc(cc).as_str()
+    match cs {
+        CharSelector::OneOf(x) => show_cc(x),
+        // there is no syntax for this one
+        CharSelector::NoneOf(CharClass::CCSingle(x)) => String::from("!") + show_char(*x).as_str(),
+        // negated classes are written in uppercase
+        CharSelector::NoneOf(x) => show_cc(x).to_ascii_uppercase(),
+    }
 }
 
 pub fn show_reject(rej: &RejectRule) -> String {
@@ -1500,16 +1506,16 @@ mod mutate {
     }
     #[test]
     fn past() {
-        let tests = vec!["bed", "beg", "ped", "poe", "pid"];
-        let expected = vec!["bed", "begged", "ped", "poed", "pided"];
+        let tests = ["bed", "beg", "ped", "poe", "pid"];
+        let expected = ["bed", "begged", "ped", "poed", "pided"];
         for (&t, &e) in tests.iter().zip(expected.iter()) {
             mut_test(t, &[PastTense], e);
         }
     }
     #[test]
     fn genitive() {
-        let tests = vec!["ping", "pang", "poo", "pan"];
-        let expected = vec!["ping", "pangging", "poing", "paning"];
+        let tests = ["ping", "pang", "poo", "pan"];
+        let expected = ["ping", "pangging", "poing", "paning"];
         for (&t, &e) in tests.iter().zip(expected.iter()) {
             mut_test(t, &[Genitive], e);
         }
@@ -1570,6 +1576,41 @@ mod mutate {
             "aSQdqdf354gdrfé&",
         );
     }
+    #[test]
+    fn rejects() {
+        let accepted = |word: &str, rj: RejectRule| {
+            mutate(
+                word.as_bytes(),
+                &[Rule::Command(CommandRule::Noop), Rule::Reject(rj)],
+            )
+            .is_some()
+        };
+        use RejectRule::*;
+        assert!(accepted("password", UnlessWordLengthMoreThan(Val(7))));
+        assert!(!accepted("passwor", UnlessWordLengthMoreThan(Val(7))));
+        assert!(accepted("pass", UnlessWordLengthLessThan(Val(5))));
+        assert!(!accepted("passw", UnlessWordLengthLessThan(Val(5))));
+        assert!(accepted("pass", UnlessWordLengthIs(Val(4))));
+        assert!(!accepted("passw", UnlessWordLengthIs(Val(4))));
+        assert!(accepted("pass1", UnlessContain(OneOf(CCDigits))));
+        assert!(!accepted("pass", UnlessContain(OneOf(CCDigits))));
+        assert!(accepted("pass", IfContain(OneOf(CCDigits))));
+        assert!(!accepted("pass1", IfContain(OneOf(CCDigits))));
+        assert!(accepted("Pass", UnlessFirstChar(OneOf(CCUpper))));
+        assert!(!accepted("pass", UnlessFirstChar(OneOf(CCUpper))));
+        assert!(accepted("pass!", UnlessLastChar(NoneOf(CCAlphaNum))));
+        assert!(!accepted("pass", UnlessLastChar(NoneOf(CCAlphaNum))));
+        assert!(accepted("pass", UnlessCharAt(Val(1), OneOf(CCVowels))));
+        assert!(!accepted("pass", UnlessCharAt(Val(2), OneOf(CCVowels))));
+        assert!(accepted(
+            "p4ss12",
+            UnlessAtLeastNTimes(Val(3), OneOf(CCDigits))
+        ));
+        assert!(!accepted(
+            "p4ss1",
+            UnlessAtLeastNTimes(Val(3), OneOf(CCDigits))
+        ));
+    }
 }
 
 #[cfg(test)]
@@ -1593,4 +1634,36 @@ mod display {
             Some("A0'lo\"l'".to_string())
         );
     }
+
+    #[test]
+    fn classes() {
+        // ?l only holds the lowercase letters, and JtR negates a class by writing it in uppercase:
+        // these used to be displayed as /?l and /!?d
+        assert_eq!(
+            show_rules(
+                &[Rule::Reject(RejectRule::UnlessContain(
+                    CharSelector::OneOf(CharClass::CCLetters)
+                ))],
+                false
+            ),
+            Some("/?a".to_string())
+        );
+        assert_eq!(
+            show_rules(
+                &[Rule::Reject(RejectRule::UnlessContain(
+                    CharSelector::NoneOf(CharClass::CCDigits)
+                ))],
+                false
+            ),
+            Some("/?D".to_string())
+        );
+    }
+
+    #[test]
+    fn insert_str_hashcat() {
+        assert_eq!(
+            show_command(&InsertString(Val(9), Vec::from("lol".as_bytes())), true),
+            Some("i9liAoiBl".to_string())
+        );
+    }
 }
@@ -0,0 +1,333 @@
+use crate::cleartexts::corpus;
+use crate::rules;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+#[derive(Clone)]
+pub struct RuleHits {
+    /// amount of candidates the rule generates on the wordlist
+    pub candidates: u64,
+    /// sorted ids of the cracked cleartexts
+    pub cracked: Vec<u64>,
+}
+
+pub type HitMap = HashMap<Vec<rules::Rule>, RuleHits>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Objective {
+    Cracks,
+    Efficiency,
+    Balanced,
+}
+
+impl std::str::FromStr for Objective {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cracks" => Ok(Objective::Cracks),
+            "efficiency" => Ok(Objective::Efficiency),
+            "balanced" => Ok(Objective::Balanced),
+            _ => Err("Accepted objectives are cracks, efficiency and balanced"),
+        }
+    }
+}
+
+impl Objective {
+    // the rules dropped by prune crack the fewest passwords, which only keeps the top rules of the
+    // cracks objective: a rule with few cracks can be the top pick of the others
+    pub fn allows_pruning(self) -> bool {
+        self == Objective::Cracks
+    }
+}
+
+// The efficiency objective models the cost of running a rule as a full pass on the wordlist
+// (one unit per word) plus the hashing of all generated candidates (hash_cost units each).
+// With a large hash_cost (slow hashes), this is almost exactly cracks per candidate.
+// The balanced objective sums the fraction of each corpus that gets cracked, so that rules
+// working on all corpora are preferred to rules that only work on the largest one.
+pub struct Scoring {
+    pub objective: Objective,
+    pub hash_cost: f64,
+    pub wordlist_len: u64,
+    // amount of cleartexts in each corpus
+    pub corpus_sizes: Vec<usize>,
+}
+
+impl Scoring {
+    pub fn score(&self, cracked: &[u64], candidates: u64) -> f64 {
+        let count = cracked.len();
+        match self.objective {
+            Objective::Cracks => count as f64,
+            Objective::Efficiency => {
+                count as f64 / (candidates as f64 * self.hash_cost + self.wordlist_len as f64)
+            }
+            Objective::Balanced => cracked
+                .iter()
+                .map(|id| {
+                    1.0 / self
+                        .corpus_sizes
+                        .get(corpus(*id))
+                        .copied()
+                        .unwrap_or(1)
+                        .max(1) as f64
+                })
+                .sum(),
+        }
+    }
+}
+
+pub struct Selected {
+    pub rules: Vec<rules::Rule>,
+    /// marginal amount of passwords cracked
+    pub count: usize,
+    /// cumulative amount of passwords cracked, including this rule
+    pub total: usize,
+    /// passwords cracked by this rule and not by the previous ones
+    pub cracked: Vec<u64>,
+    pub candidates: u64,
+}
+
+pub fn shorter_rules(a: &[rules::Rule], b: &[rules::Rule]) -> bool {
+    let la = rules::show_rules(a, false).map(|x| x.len());
+    let lb = rules::show_rules(b, false).map(|x| x.len());
+    la < lb || (la == lb && a < b)
+}
+
+pub fn sub_set(a: &[u64], b: &[u64]) -> Vec<u64> {
+    let mut o = Vec::new();
+    let mut ai = a.iter();
+    let mut bi = b.iter();
+    let mut ma = ai.next();
+    let mut mb = bi.next();
+    loop {
+        match (ma, mb) {
+            (Some(cura), Some(curb)) => match cura.cmp(curb) {
+                Ordering::Equal => {
+                    ma = ai.next();
+                    mb = bi.next();
+                }
+                Ordering::Greater => {
+                    mb = bi.next();
+                }
+                Ordering::Less => {
+                    o.push(*cura);
+                    ma = ai.next();
+                }
+            },
+            (None, _) => break,
+            (Some(cura), None) => {
+                o.push(*cura);
+                ma = ai.next();
+            }
+        }
+    }
+    o
+}
+
+// drops the rules cracking the fewest passwords so that at most max_hits rules are retained, and
+// returns the minimum amount of cracks of the retained rules: the greedy selection using it as a
+// cutoff gives the same results as if no rules were dropped
+pub fn prune(hits: &mut HitMap, max_hits: usize) -> usize {
+    let mut counts: Vec<usize> = hits.values().map(|h| h.cracked.len()).collect();
+    if counts.len() <= max_hits {
+        return counts.iter().cloned().min().unwrap_or(0);
+    }
+    counts.sort_unstable_by(|a, b| b.cmp(a));
+    let threshold = counts[max_hits] + 1;
+    hits.retain(|_, h| h.cracked.len() >= threshold);
+    threshold
+}
+
+// greedy coverage, consumes the hits and calls report for each selected rule, the selection
+// stops early when report returns false
+pub fn greedy<F: FnMut(&Selected) -> bool>(
+    hits: &mut HitMap,
+    cutoff: usize,
+    scoring: &Scoring,
+    mut report: F,
+) {
+    let mut last_set: Vec<u64> = Vec::new();
+    let mut total_cracked = 0;
+    while !hits.is_empty() {
+        let mut best_rules: Vec<rules::Rule> = vec![];
+        let mut best_count: usize = 0;
+        let mut best_score: f64 = 0.0;
+        let mut best_candidates: u64 = 0;
+        let mut best_set: Vec<u64> = Vec::new();
+        let mut to_remove: Vec<Vec<rules::Rule>> = Vec::new();
+        for im in hits.iter_mut() {
+            // early cutoff
+            if im.1.cracked.len() < cutoff {
+                to_remove.push(im.0.clone());
+                continue;
+            }
+            im.1.cracked = sub_set(&im.1.cracked, &last_set);
+            // deferred cutoff
+            if im.1.cracked.len() < cutoff {
+                to_remove.push(im.0.clone());
+                continue;
+            }
+            let curlen = im.1.cracked.len();
+            if curlen == 0 {
+                continue;
+            }
+            let curscore = scoring.score(&im.1.cracked, im.1.candidates);
+            if curscore > best_score || (curscore == best_score && shorter_rules(im.0, &best_rules))
+            {
+                best_count = curlen;
+                best_score = curscore;
+                best_candidates = im.1.candidates;
+                best_rules = im.0.clone();
+                best_set = im.1.cracked.clone();
+            }
+        }
+        for rm in to_remove {
+            hits.remove(&rm);
+        }
+        if best_count == 0 {
+            // only happens with a null cutoff, when all remaining rules are useless
+            break;
+        }
+        hits.remove(&best_rules);
+        total_cracked += best_count;
+        let proceed = report(&Selected {
+            rules: best_rules,
+            count: best_count,
+            total: total_cracked,
+            cracked: best_set.clone(),
+            candidates: best_candidates,
+        });
+        last_set = best_set;
+        if !proceed {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::CommandRule::*;
+    use crate::rules::Rule::*;
+
+    fn run(hits: &mut HitMap, scoring: &Scoring) -> Vec<(Vec<rules::Rule>, usize)> {
+        let mut out = Vec::new();
+        greedy(hits, 1, scoring, |s| {
+            out.push((s.rules.clone(), s.count));
+            true
+        });
+        out
+    }
+
+    fn sample() -> HitMap {
+        let mut hits = HashMap::new();
+        hits.insert(
+            vec![Command(Append(b'1'))],
+            RuleHits {
+                candidates: 1000,
+                cracked: vec![0, 1, 2, 3],
+            },
+        );
+        hits.insert(
+            vec![Command(Append(b'2'))],
+            RuleHits {
+                candidates: 10,
+                cracked: vec![3, 4],
+            },
+        );
+        hits
+    }
+
+    #[test]
+    fn cracks() {
+        let scoring = Scoring {
+            objective: Objective::Cracks,
+            hash_cost: 1.0,
+            wordlist_len: 1000,
+            corpus_sizes: Vec::new(),
+        };
+        let res = run(&mut sample(), &scoring);
+        assert_eq!(
+            res,
+            vec![
+                (vec![Command(Append(b'1'))], 4),
+                (vec![Command(Append(b'2'))], 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn pruning() {
+        let mut hits = sample();
+        hits.insert(
+            vec![Command(Append(b'3'))],
+            RuleHits {
+                candidates: 10,
+                cracked: vec![5, 6],
+            },
+        );
+        let threshold = prune(&mut hits, 2);
+        assert_eq!(threshold, 3);
+        assert_eq!(hits.len(), 1);
+        assert!(hits.contains_key(&vec![Command(Append(b'1'))]));
+
+        // $2 is the top pick of the efficiency objective, but gets pruned
+        let scoring = Scoring {
+            objective: Objective::Efficiency,
+            hash_cost: 1000.0,
+            wordlist_len: 1000,
+            corpus_sizes: Vec::new(),
+        };
+        let mut pruned = sample();
+        prune(&mut pruned, 1);
+        assert_ne!(
+            run(&mut pruned, &scoring)[0],
+            run(&mut sample(), &scoring)[0]
+        );
+        assert!(Objective::Cracks.allows_pruning());
+        assert!(!Objective::Efficiency.allows_pruning());
+        assert!(!Objective::Balanced.allows_pruning());
+    }
+
+    #[test]
+    fn efficiency() {
+        let scoring = Scoring {
+            objective: Objective::Efficiency,
+            hash_cost: 1000.0,
+            wordlist_len: 1000,
+            corpus_sizes: Vec::new(),
+        };
+        let res = run(&mut sample(), &scoring);
+        assert_eq!(
+            res,
+            vec![
+                (vec![Command(Append(b'2'))], 2),
+                (vec![Command(Append(b'1'))], 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn balanced() {
+        let second = 1 << 40;
+        let mut hits = sample();
+        // cracks few passwords, but in the small corpus
+        hits.insert(
+            vec![Command(Append(b'3'))],
+            RuleHits {
+                candidates: 10,
+                cracked: vec![second, second + 1],
+            },
+        );
+        let scoring = Scoring {
+            objective: Objective::Balanced,
+            hash_cost: 1.0,
+            wordlist_len: 1000,
+            corpus_sizes: vec![100, 4],
+        };
+        assert_eq!(scoring.score(&[0, 1, second], 10), 0.27);
+        let res = run(&mut hits, &scoring);
+        assert_eq!(res[0], (vec![Command(Append(b'3'))], 2));
+        assert_eq!(res[1], (vec![Command(Append(b'1'))], 4));
+    }
+}
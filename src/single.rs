@@ -0,0 +1,144 @@
+use crate::cleartexts::CleartextInfo;
+use crate::compiled;
+use crate::matcher::{finish_hits, record_matches, WorkerHits};
+use crate::rules;
+use smallvec::ToSmallVec;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+// JtR single mode and hashcat association attacks mangle the data of each account, such as its
+// login and GECOS fields. The rules are run on the words of each account, and only matched with
+// the password of that account.
+
+pub struct Account {
+    pub words: Vec<Vec<u8>>,
+    pub password: Vec<u8>,
+}
+
+// the login, and the words of the extra fields, such as "John Smith" or "jsmith@example.com"
+fn account_words(login: &[u8], extra: &[&[u8]]) -> Vec<Vec<u8>> {
+    let mut words = vec![login.to_vec()];
+    for field in extra {
+        for w in field.split(|c| !c.is_ascii_alphanumeric() && *c < 128) {
+            if !w.is_empty() && !words.iter().any(|x| x == w) {
+                words.push(w.to_vec());
+            }
+        }
+    }
+    words
+}
+
+// reads login:password[:extra fields] records, skipping the passwords that are too short, the
+// account ids being the line numbers
+pub fn read(path: &Path, minsize: usize) -> io::Result<(Vec<(u64, Account)>, usize)> {
+    let file = File::open(path)?;
+    let rdr = io::BufReader::new(file);
+    let mut accounts = Vec::new();
+    let mut invalid = 0;
+    for (i, rawline) in rdr.split(b'\n').enumerate() {
+        let mut line = rawline?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        let fields: Vec<&[u8]> = line.split(|c| *c == b':').collect();
+        if fields.len() < 2 || fields[0].is_empty() {
+            invalid += 1;
+            continue;
+        }
+        if fields[1].len() < minsize {
+            continue;
+        }
+        accounts.push((
+            i as u64,
+            Account {
+                words: account_words(fields[0], &fields[2..]),
+                password: fields[1].to_vec(),
+            },
+        ));
+    }
+    Ok((accounts, invalid))
+}
+
+pub fn worker_logic(
+    rules: Vec<rules::Rule>,
+    accounts: &[(u64, Account)],
+    minsize: usize,
+    cutoff: usize,
+) -> WorkerHits {
+    let mut hits: HashMap<Vec<rules::Rule>, BTreeSet<u64>> = HashMap::new();
+    let mut candidates = 0;
+    let program = compiled::compile(&rules);
+    let mut executor = compiled::Executor::new();
+    let mut matches: Vec<CleartextInfo> = Vec::new();
+    for (id, account) in accounts {
+        let password = &account.password;
+        for word in &account.words {
+            let mutated = match executor.run(&program, word) {
+                None => continue,
+                Some(m) => m,
+            };
+            candidates += 1;
+            if mutated.len() < minsize || mutated.len() > password.len() {
+                continue;
+            }
+            // the password of the account plays the part of the fragment index
+            matches.clear();
+            for start in 0..=password.len() - mutated.len() {
+                if &password[start..start + mutated.len()] == mutated {
+                    matches.push((
+                        password[..start].to_smallvec(),
+                        password[start + mutated.len()..].to_smallvec(),
+                        *id,
+                    ));
+                }
+            }
+            record_matches(&mut hits, &rules, &matches);
+        }
+    }
+    (finish_hits(hits, cutoff), candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::CommandRule::*;
+    use crate::rules::Rule::Command;
+
+    #[test]
+    fn single() {
+        let account = |login: &str, password: &str, extra: &[&str]| Account {
+            words: account_words(
+                login.as_bytes(),
+                &extra.iter().map(|e| e.as_bytes()).collect::<Vec<_>>(),
+            ),
+            password: password.as_bytes().to_vec(),
+        };
+        let accounts = vec![
+            (0, account("jsmith", "Jsmith1", &["John Smith"])),
+            (1, account("alice", "Alice1", &["alice@example.com"])),
+            // the other accounts' words are not used
+            (2, account("bob", "Jsmith2", &[])),
+            (3, account("carol", "Smith", &["Carol Smith,room 12"])),
+        ];
+        assert_eq!(
+            accounts[3].1.words,
+            vec![
+                b"carol".to_vec(),
+                b"Carol".to_vec(),
+                b"Smith".to_vec(),
+                b"room".to_vec(),
+                b"12".to_vec()
+            ]
+        );
+        let (hits, candidates) = worker_logic(vec![Command(Capitalize)], &accounts, 4, 1);
+        assert_eq!(candidates, 12);
+        assert_eq!(
+            hits[&vec![Command(Capitalize), Command(Append(b'1'))]],
+            vec![0, 1]
+        );
+        assert_eq!(hits[&vec![Command(Capitalize)]], vec![3]);
+        assert_eq!(hits.len(), 2);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::cleartexts::CleartextMap;
+use crate::rules::{CharClass, CharSelector, CommandRule, Rule};
+use std::collections::{HashMap, HashSet};
+
+// Substitution habits (a -> @, s -> $ ...) differ between leaks, so they are mined from the corpus:
+// a sample of the wordlist is looked up in the fragment index with one letter replaced by a digit
+// or a symbol, and the substitutions are ranked by the amount of cleartexts they were found in.
+
+pub struct Substitution {
+    pub from: u8,
+    pub to: u8,
+    pub cleartexts: usize,
+}
+
+fn is_target(c: u8) -> bool {
+    c.is_ascii_graphic() && !c.is_ascii_alphabetic()
+}
+
+// substitutions found in at least two cleartexts, the most frequent first
+pub fn mine(wordlist: &[Vec<u8>], aclear: &CleartextMap, max_sampled: usize) -> Vec<Substitution> {
+    let mut found: HashMap<(u8, u8), HashSet<u64>> = HashMap::new();
+    let step = (wordlist.len() / max_sampled.max(1)).max(1);
+    let mut cur = Vec::new();
+    for word in wordlist.iter().step_by(step).take(max_sampled) {
+        cur.clear();
+        cur.extend_from_slice(word);
+        for (i, &from) in word.iter().enumerate() {
+            if !from.is_ascii_alphabetic() {
+                continue;
+            }
+            for to in (0..=255u8).filter(|c| is_target(*c)) {
+                cur[i] = to;
+                if let Some(matches) = aclear.get(&cur[..]) {
+                    found
+                        .entry((from, to))
+                        .or_default()
+                        .extend(matches.iter().map(|m| m.2));
+                }
+            }
+            cur[i] = from;
+        }
+    }
+    let mut subs: Vec<Substitution> = found
+        .into_iter()
+        .filter(|(_, ids)| ids.len() > 1)
+        .map(|((from, to), ids)| Substitution {
+            from,
+            to,
+            cleartexts: ids.len(),
+        })
+        .collect();
+    subs.sort_by_key(|s| (std::cmp::Reverse(s.cleartexts), s.from, s.to));
+    subs
+}
+
+fn replace_all(s: &Substitution) -> Rule {
+    Rule::Command(CommandRule::ReplaceAll(
+        CharSelector::OneOf(CharClass::CCSingle(s.from)),
+        s.to,
+    ))
+}
+
+// a rule for each substitution, and the chains of the most frequent substitutions, a letter being
+// only replaced by its most frequent substitute
+pub fn substitution_rules(subs: &[Substitution]) -> Vec<Vec<Rule>> {
+    let mut out: Vec<Vec<Rule>> = subs.iter().map(|s| vec![replace_all(s)]).collect();
+    let mut chain = Vec::new();
+    let mut used = HashSet::new();
+    for s in subs {
+        if used.insert(s.from) {
+            chain.push(replace_all(s));
+            if chain.len() > 1 {
+                out.push(chain.clone());
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cleartexts;
+    use crate::rules;
+
+    fn conv(i: &str) -> Vec<u8> {
+        i.as_bytes().to_vec()
+    }
+
+    #[test]
+    fn mining() {
+        let wordlist: Vec<Vec<u8>> = ["password", "sunshine", "letmein", "master", "dragon"]
+            .iter()
+            .map(|w| conv(w))
+            .collect();
+        let clears = [
+            "p@ssword1",
+            "p@ssw0rd",
+            "dr@gon",
+            "$unshine",
+            "pa$$word",
+            "m@ster!",
+            "l3tmein",
+            "passw0rd",
+            "let7ein",
+            "drag0n",
+        ];
+        let mut aclear = HashMap::new();
+        for (i, c) in clears.iter().enumerate() {
+            cleartexts::process_line(&mut aclear, i as u64, &conv(c), 4);
+        }
+        let subs = mine(&wordlist, &aclear, 100);
+        let pairs: Vec<(u8, u8, usize)> =
+            subs.iter().map(|s| (s.from, s.to, s.cleartexts)).collect();
+        assert_eq!(pairs, vec![(b'a', b'@', 3), (b'o', b'0', 2)]);
+        let rls = substitution_rules(&subs);
+        assert_eq!(rls.len(), 3);
+        assert_eq!(
+            rules::mutate(b"password", &rls[2]).unwrap(),
+            conv("p@ssw0rd")
+        );
+    }
+}